@@ -7,6 +7,7 @@ use json_minimal::Json;
 
 use log::info;
 
+use crate::atomic;
 use crate::gallery::{Gallery, Tag, TagType};
 use crate::version::get_version;
 
@@ -111,7 +112,9 @@ pub fn make_cover<P: AsRef<Path>>(path: P) -> Result<PathBuf, Error> {
         .unwrap()
         .with_file_name(format!("cover.{}", ext));
 
-    let written = fs::copy(path, &cover)?;
+    let temp = atomic::create(&cover)?;
+    let written = fs::copy(path, temp.path())?;
+    atomic::persist(temp, &cover)?;
 
     info!("Written {:?} ({} bytes written)", cover, written);
     Ok(cover)
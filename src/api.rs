@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+use log::{debug, info};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+
+use crate::gallery::Gallery;
+use crate::http;
+use crate::ratelimit::Budget;
+use crate::retry::{self, is_retryable_request, Retryable};
+
+pub const DEFAULT_API_URL: &str = "https://api.e-hentai.org/api.php";
+/// The most galleries `gdata` accepts in a single request
+const BATCH_SIZE: usize = 25;
+
+#[derive(Debug)]
+pub enum ApiError {
+    NetworkError(reqwest::Error),
+    BytesDecodeError(reqwest::Error),
+    JsonError(serde_json::Error),
+    GalleryError(u64, String),
+    MissingGallery(u64),
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "error while {}",
+            match self {
+                Self::NetworkError(e) => format!("performing api request: {}", e),
+                Self::BytesDecodeError(e) => format!("decoding api response: {}", e),
+                Self::JsonError(e) => format!("parsing api response: {}", e),
+                Self::GalleryError(gid, e) => format!("getting metadata for {}: {}", gid, e),
+                Self::MissingGallery(gid) => {
+                    format!("getting metadata for {}: not in api response", gid)
+                }
+            }
+        )
+    }
+}
+
+impl Retryable for ApiError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::NetworkError(e) | Self::BytesDecodeError(e) => is_retryable_request(e),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GdataRequest {
+    method: &'static str,
+    gidlist: Vec<(u64, String)>,
+    namespace: u8,
+}
+
+#[derive(Deserialize)]
+struct GdataResponse {
+    gmetadata: Vec<GalleryMetadata>,
+}
+
+/// A single entry of `gmetadata`. Galleries the api couldn't
+/// look up only come with `gid` and `error`, so everything else
+/// falls back to its default
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GalleryMetadata {
+    pub gid: u64,
+    pub token: String,
+    pub error: Option<String>,
+    pub title: String,
+    pub title_jpn: String,
+    pub category: String,
+    pub uploader: String,
+    pub posted: String,
+    pub filecount: String,
+    pub filesize: u64,
+    pub rating: String,
+    pub expunged: bool,
+    pub tags: Vec<String>,
+}
+
+impl GalleryMetadata {
+    /// Copies the metadata into `gallery`. Tags come namespaced
+    /// (`artist:foo`), tags without a namespace are misc tags.
+    ///
+    /// The api escapes text the way the gallery page's HTML does, so
+    /// it's unescaped here to end up the same as when it's scraped
+    pub fn apply(&self, gallery: &mut Gallery) {
+        gallery.set_title(unescape(&self.title));
+        gallery.set_title_jpn(unescape(&self.title_jpn));
+        gallery.set_category(self.category.clone());
+        gallery.set_uploader(unescape(&self.uploader));
+        gallery.set_posted(self.posted.parse().ok());
+        gallery.set_file_count(self.filecount.parse().ok());
+        gallery.set_file_size(Some(self.filesize));
+        gallery.set_rating(self.rating.parse().ok());
+
+        for tag in &self.tags {
+            let (name, value) = tag.split_once(':').unwrap_or(("other", tag));
+            gallery.add_tag(name.to_string(), value.to_string());
+        }
+    }
+
+    /// Turns the `error` the api gives for galleries
+    /// it couldn't look up into an `Err`
+    fn checked(self) -> Result<Self, ApiError> {
+        match self.error {
+            Some(e) => Err(ApiError::GalleryError(self.gid, e)),
+            None => Ok(self),
+        }
+    }
+}
+
+/// Replaces the HTML entities in `raw` (`&amp;`, `&#039;`, `&#x27;`...)
+/// with the characters they stand for. Anything that isn't a known
+/// entity is left as it is
+fn unescape(raw: &str) -> String {
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut rest = raw;
+
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .map(|end| &rest[1..end + 1])
+            .and_then(|entity| Some((entity, decode_entity(entity)?)));
+        match entity {
+            Some((entity, c)) => {
+                unescaped.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+
+    unescaped
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+
+            char::from_u32(code)
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref CACHE: Mutex<HashMap<u64, GalleryMetadata>> = Mutex::new(HashMap::new());
+}
+
+/// Looks up all of `ids` in as few requests as possible and keeps
+/// the results around for `get_metadata`
+pub async fn prefetch(ids: &[(u64, String)]) -> Result<(), ApiError> {
+    let url = get_api_url();
+
+    for batch in ids.chunks(BATCH_SIZE) {
+        let metadata = fetch_batch(&url, batch).await?;
+
+        CACHE
+            .lock()
+            .unwrap()
+            .extend(metadata.into_iter().map(|m| (m.gid, m)));
+    }
+
+    Ok(())
+}
+
+pub async fn get_metadata(gid: u64, token: &str) -> Result<GalleryMetadata, ApiError> {
+    let cached = CACHE.lock().unwrap().remove(&gid);
+
+    let metadata = match cached {
+        Some(metadata) => metadata,
+        None => fetch_batch(&get_api_url(), &[(gid, token.to_string())])
+            .await?
+            .into_iter()
+            .find(|m| m.gid == gid)
+            .ok_or(ApiError::MissingGallery(gid))?,
+    };
+
+    metadata.checked()
+}
+
+async fn fetch_batch(url: &str, ids: &[(u64, String)]) -> Result<Vec<GalleryMetadata>, ApiError> {
+    let request = GdataRequest {
+        method: "gdata",
+        gidlist: ids.to_vec(),
+        namespace: 1,
+    };
+    let body = serde_json::to_string(&request).map_err(ApiError::JsonError)?;
+
+    info!("Requesting metadata for {} gallery(s)", ids.len());
+    retry::get_policy()
+        .run(url, || send_batch(url, body.clone()))
+        .await
+}
+
+async fn send_batch(url: &str, body: String) -> Result<Vec<GalleryMetadata>, ApiError> {
+    let request = http::client()
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body);
+
+    let resp = http::send(request, Budget::Html)
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(ApiError::NetworkError)?;
+    debug!("POST {} => {}", url, resp.status());
+
+    let bytes = resp.bytes().await.map_err(ApiError::BytesDecodeError)?;
+    let resp = serde_json::from_slice::<GdataResponse>(&bytes).map_err(ApiError::JsonError)?;
+
+    Ok(resp.gmetadata)
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        fn get_api_url() -> String {
+            crate::CONFIG.api.url.clone()
+        }
+
+        pub fn is_enabled() -> bool {
+            crate::CONFIG.api.enabled
+        }
+    } else if #[cfg(feature = "cli")] {
+        fn get_api_url() -> String {
+            crate::ARGS.api_url.clone()
+        }
+
+        pub fn is_enabled() -> bool {
+            !crate::ARGS.no_api
+        }
+    } else {
+        fn get_api_url() -> String {
+            std::env::var("EH_API_URL").unwrap_or(DEFAULT_API_URL.to_string())
+        }
+
+        pub fn is_enabled() -> bool {
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{prelude::*, BufReader};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const GID: u64 = 618395;
+    /// Answered with an `error`, like galleries with a wrong key are
+    const BROKEN_GID: u64 = 1;
+
+    /// Answers `gdata` requests on a local port with canned
+    /// metadata, returning the url to send them to
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/api.php", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                answer(stream);
+            }
+        });
+
+        url
+    }
+
+    fn answer(mut stream: TcpStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+
+            if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                len = value.trim().parse().unwrap();
+            }
+        }
+
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        let request = serde_json::from_slice::<Value>(&body).unwrap();
+
+        let gmetadata = request["gidlist"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|id| match id[0].as_u64().unwrap() {
+                BROKEN_GID => json!({
+                    "gid": BROKEN_GID,
+                    "error": "Key missing, or incorrect key provided."
+                }),
+                gid => json!({
+                    "gid": gid,
+                    "token": id[1],
+                    "title": "Tom &amp; Jerry&#039;s Day Out [English]",
+                    "title_jpn": "&lt;トムとジェリー&gt;",
+                    "category": "Doujinshi",
+                    "uploader": "someone",
+                    "posted": "1376143500",
+                    "filecount": "20",
+                    "filesize": 51210504,
+                    "rating": "4.43",
+                    "expunged": false,
+                    "tags": ["artist:foo bar", "language:english", "misc"]
+                }),
+            })
+            .collect::<Vec<_>>();
+
+        let body = json!({ "gmetadata": gmetadata }).to_string();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+    }
+
+    // The shared client, retry policy and rate limiter all
+    // leave the command line and config alone under test
+    #[tokio::test]
+    async fn metadata_is_applied_and_errors_are_reported() {
+        let ids = [
+            (GID, "abcdef0123".to_string()),
+            (BROKEN_GID, "0000000000".to_string()),
+        ];
+        let mut metadata = fetch_batch(&serve(), &ids).await.unwrap().into_iter();
+
+        let mut gallery = Gallery::new();
+        metadata
+            .next()
+            .unwrap()
+            .checked()
+            .unwrap()
+            .apply(&mut gallery);
+
+        assert_eq!(gallery.title(), "Tom & Jerry's Day Out [English]");
+        assert_eq!(gallery.title_jpn(), "<トムとジェリー>");
+        assert_eq!(gallery.category(), "Doujinshi");
+        assert_eq!(gallery.uploader(), "someone");
+        assert_eq!(gallery.posted(), Some(1376143500));
+        assert_eq!(gallery.file_count(), Some(20));
+        assert_eq!(gallery.file_size(), Some(51210504));
+        assert_eq!(gallery.rating(), Some(4.43));
+
+        let tags = gallery
+            .tags()
+            .into_iter()
+            .map(|tag| format!("{}:{}", tag.tag_type().to_string(), tag.tag_value()))
+            .collect::<Vec<_>>();
+        assert!(tags.contains(&"artist:foo bar".to_string()));
+        assert!(tags.contains(&"language:english".to_string()));
+        assert!(tags.contains(&"other:misc".to_string()));
+
+        assert!(matches!(
+            metadata.next().unwrap().checked(),
+            Err(ApiError::GalleryError(BROKEN_GID, _))
+        ));
+    }
+
+    #[test]
+    fn entities_are_unescaped() {
+        assert_eq!(unescape("a &amp; b"), "a & b");
+        assert_eq!(unescape("&#039;&#x27;&quot;"), "''\"");
+        assert_eq!(unescape("&lt;b&gt;"), "<b>");
+        assert_eq!(
+            unescape("AT&T &unknown; &#xZZ; &"),
+            "AT&T &unknown; &#xZZ; &"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use tempfile::{Builder, NamedTempFile};
+
+/// Temporary files are named `.ehdl-<random>`, so that
+/// the ones left behind by a crash can be told apart
+pub const TEMP_PREFIX: &str = ".ehdl-";
+/// Images are downloaded to `<name>.part` first. Unlike temporary
+/// files these are kept around, as the download can resume from them
+pub const PART_SUFFIX: &str = ".part";
+
+/// A temporary file in the same directory as `path`, so
+/// that it can be renamed to it once it's written
+pub fn create(path: &Path) -> Result<NamedTempFile, io::Error> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    Builder::new().prefix(TEMP_PREFIX).tempfile_in(dir)
+}
+
+/// Moves `file` over `path`
+pub fn persist(file: NamedTempFile, path: &Path) -> Result<(), io::Error> {
+    file.persist(path).map_err(|e| e.error)?;
+
+    debug!("Persisted {:?}", path);
+    Ok(())
+}
+
+/// Where the image that ends up at `path` is downloaded to
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(PART_SUFFIX);
+
+    path.with_file_name(name)
+}
+
+/// Removes the temporary files left in `root` by runs that didn't
+/// get to rename them, looking `depth` directories deep
+pub fn clean(root: &Path, depth: usize) {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            warn!("Unable to look for temporary files in {:?}: {}", root, e);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        if file_type.is_dir() && depth > 0 {
+            clean(&path, depth - 1);
+        } else if file_type.is_file()
+            && entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX)
+        {
+            match fs::remove_file(&path) {
+                Ok(_) => info!("Removed stale temporary file {:?}", path),
+                Err(e) => warn!("Unable to remove stale temporary file {:?}: {}", path, e),
+            }
+        }
+    }
+}
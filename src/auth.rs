@@ -0,0 +1,229 @@
+use std::env::var;
+use std::fmt::{Debug, Display};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use reqwest::cookie::Jar;
+use reqwest::Url;
+
+const SITES: [&str; 2] = ["https://e-hentai.org", "https://exhentai.org"];
+
+#[derive(Debug)]
+pub enum AuthError {
+    ReadError(PathBuf, std::io::Error),
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "error while {}",
+            match self {
+                Self::ReadError(p, e) => format!("reading cookies file {:?}: {}", p, e),
+            }
+        )
+    }
+}
+
+/// The session cookies the site uses to recognize an account.
+/// `igneous` is only needed (and only issued) for ExHentai
+#[derive(Clone, Default)]
+pub struct Credentials {
+    member_id: Option<String>,
+    pass_hash: Option<String>,
+    igneous: Option<String>,
+}
+
+// Manually implemented so that the session never ends up in the logs
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("member_id", &self.member_id)
+            .field("pass_hash", &self.pass_hash.as_ref().map(|_| "<redacted>"))
+            .field("igneous", &self.igneous.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl Credentials {
+    pub fn is_logged_in(&self) -> bool {
+        self.member_id.is_some() && self.pass_hash.is_some()
+    }
+
+    /// Fills in whatever is still missing from `other`
+    fn or(self, other: Self) -> Self {
+        Self {
+            member_id: self.member_id.or(other.member_id),
+            pass_hash: self.pass_hash.or(other.pass_hash),
+            igneous: self.igneous.or(other.igneous),
+        }
+    }
+
+    fn from_env() -> Self {
+        Self {
+            member_id: var("EH_MEMBER_ID").ok(),
+            pass_hash: var("EH_PASS_HASH").ok(),
+            igneous: var("EH_IGNEOUS").ok(),
+        }
+    }
+
+    /// Reads the site's cookies out of a Netscape `cookies.txt`,
+    /// the format most browser cookie exporters produce
+    fn from_cookies_file<P: AsRef<Path>>(path: P) -> Result<Self, AuthError> {
+        let path = path.as_ref();
+        let raw = read_to_string(path).map_err(|e| AuthError::ReadError(path.to_path_buf(), e))?;
+        let mut creds = Self::default();
+
+        for line in raw.lines() {
+            // `#HttpOnly_` marks an HttpOnly cookie, not a comment
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.starts_with('#') {
+                continue;
+            }
+
+            // domain, subdomains, path, secure, expiry, name, value
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            if fields.len() != 7 {
+                continue;
+            }
+
+            let domain = fields[0].trim_start_matches('.');
+            if domain != "e-hentai.org" && domain != "exhentai.org" {
+                continue;
+            }
+
+            let value = Some(fields[6].trim().to_string());
+            match fields[5] {
+                "ipb_member_id" => creds.member_id = value,
+                "ipb_pass_hash" => creds.pass_hash = value,
+                "igneous" if fields[6].trim() != "mystery" => creds.igneous = value,
+                _ => {}
+            }
+        }
+
+        Ok(creds)
+    }
+
+    /// Seeds `jar` with these credentials for both sites
+    pub fn apply(&self, jar: &Jar) {
+        let cookies = [
+            ("ipb_member_id", &self.member_id),
+            ("ipb_pass_hash", &self.pass_hash),
+            ("igneous", &self.igneous),
+        ];
+
+        for site in SITES {
+            let url = site.parse::<Url>().unwrap();
+
+            for (name, value) in cookies.iter() {
+                if let Some(value) = value {
+                    jar.add_cookie_str(&format!("{}={}; Path=/", name, value), &url);
+                }
+            }
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref CREDENTIALS: Credentials = load_credentials();
+}
+
+pub fn get_credentials() -> &'static Credentials {
+    &CREDENTIALS
+}
+
+/// Environment variables win over the config, which wins over the cookies file
+fn load_credentials() -> Credentials {
+    let mut creds = Credentials::from_env().or(get_configured());
+
+    if let Some(file) = get_cookies_file() {
+        match Credentials::from_cookies_file(&file) {
+            Ok(from_file) => creds = creds.or(from_file),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    if creds.is_logged_in() {
+        info!(
+            "Logged in as member {}",
+            creds.member_id.as_deref().unwrap_or_default()
+        );
+    } else {
+        info!("No credentials provided, ExHentai galleries will be unavailable");
+    }
+
+    creds
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "config", not(test)))] {
+        fn get_configured() -> Credentials {
+            let auth = &crate::CONFIG.auth;
+
+            Credentials {
+                member_id: auth.member_id.clone(),
+                pass_hash: auth.pass_hash.clone(),
+                igneous: auth.igneous.clone(),
+            }
+        }
+
+        fn get_cookies_file() -> Option<PathBuf> {
+            crate::CONFIG.auth.cookies.clone()
+        }
+    } else if #[cfg(all(feature = "cli", not(test)))] {
+        fn get_configured() -> Credentials {
+            Credentials::default()
+        }
+
+        fn get_cookies_file() -> Option<PathBuf> {
+            crate::ARGS.cookies.clone()
+        }
+    } else {
+        fn get_configured() -> Credentials {
+            Credentials::default()
+        }
+
+        fn get_cookies_file() -> Option<PathBuf> {
+            var("EH_COOKIES").ok().map(PathBuf::from)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_is_read_from_cookies_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cookies.txt");
+        std::fs::write(
+            &path,
+            "# Netscape HTTP Cookie File\n\
+             .e-hentai.org\tTRUE\t/\tFALSE\t0\tipb_member_id\t1234\n\
+             #HttpOnly_.e-hentai.org\tTRUE\t/\tTRUE\t0\tipb_pass_hash\tabcdef\n\
+             .exhentai.org\tTRUE\t/\tFALSE\t0\tigneous\tmystery\n\
+             .example.org\tTRUE\t/\tFALSE\t0\tipb_member_id\t9999\n\
+             # .e-hentai.org\tTRUE\t/\tFALSE\t0\tipb_member_id\t5678\n\
+             broken line\n",
+        )
+        .unwrap();
+
+        let creds = Credentials::from_cookies_file(&path).unwrap();
+        assert!(creds.is_logged_in());
+        assert_eq!(creds.member_id.as_deref(), Some("1234"));
+        assert_eq!(creds.pass_hash.as_deref(), Some("abcdef"));
+        // ExHentai hands out "mystery" to accounts it doesn't let in
+        assert_eq!(creds.igneous, None);
+    }
+
+    #[test]
+    fn missing_cookies_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            Credentials::from_cookies_file(dir.path().join("cookies.txt")),
+            Err(AuthError::ReadError(..))
+        ));
+    }
+}
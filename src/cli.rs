@@ -1,6 +1,28 @@
 #[cfg(feature = "cli")]
+use crate::conflict::ConflictPolicy;
+#[cfg(feature = "cli")]
+use crate::downloader::{DEFAULT_FAILOVERS, DEFAULT_WORKERS};
+#[cfg(feature = "cli")]
+use crate::http::{DEFAULT_CONNECT_TIMEOUT, DEFAULT_READ_TIMEOUT, DEFAULT_USER_AGENT};
+#[cfg(feature = "cli")]
 use crate::logger::{parse_log_level, LogLevel};
 #[cfg(feature = "cli")]
+use crate::naming::NamingMode;
+#[cfg(feature = "cli")]
+use crate::quota::DEFAULT_PAUSE;
+#[cfg(feature = "cli")]
+use crate::ratelimit::{
+    DEFAULT_HTML_BURST, DEFAULT_HTML_RATE, DEFAULT_IMAGE_BURST, DEFAULT_IMAGE_RATE,
+};
+#[cfg(feature = "cli")]
+use crate::retry::{DEFAULT_ATTEMPTS, DEFAULT_BACKOFF, DEFAULT_MAX_BACKOFF};
+#[cfg(feature = "cli")]
+use crate::scheduler::DEFAULT_GALLERY_WORKERS;
+#[cfg(feature = "cli")]
+use crate::state::DEFAULT_STATE_FILE;
+#[cfg(feature = "cli")]
+use crate::template::{DEFAULT_OUTPUT, DEFAULT_TEMPLATE};
+#[cfg(feature = "cli")]
 use clap::Parser;
 #[cfg(feature = "cli")]
 use std::path::PathBuf;
@@ -20,6 +42,134 @@ pub struct Args {
     #[arg(short = 'D', long = "description")]
     pub description: Option<String>,
 
-    #[arg(value_name = "INPUT_FILE", value_hint = clap::ValueHint::FilePath)]
-    pub links_file: PathBuf,
+    #[arg(short = 'j', long = "workers", default_value_t = DEFAULT_WORKERS)]
+    pub workers: usize,
+
+    /// How many galleries are downloaded at the same time
+    #[arg(short = 'g', long = "galleries", default_value_t = DEFAULT_GALLERY_WORKERS)]
+    pub galleries: usize,
+
+    /// Journal of what has been downloaded so far, used to resume
+    #[arg(long = "state", default_value = DEFAULT_STATE_FILE)]
+    pub state: PathBuf,
+
+    /// What to do with galleries that were downloaded before
+    #[arg(long = "on-conflict", value_enum, default_value_t = ConflictPolicy::default())]
+    pub conflict: ConflictPolicy,
+
+    /// Directory galleries are downloaded into
+    #[arg(short = 'o', long = "output", default_value = DEFAULT_OUTPUT)]
+    pub output: PathBuf,
+
+    /// Where in the output directory a gallery goes, e.g.
+    /// "{category}/{artist}/{title} [{gid}]"
+    #[arg(short = 't', long = "template", default_value = DEFAULT_TEMPLATE)]
+    pub template: String,
+
+    /// How images are named on disk
+    #[arg(long = "naming", value_enum, default_value_t = NamingMode::default())]
+    pub naming: NamingMode,
+
+    /// Download original images instead of the resampled ones,
+    /// which takes GP from the account's quota
+    #[arg(long = "originals")]
+    pub originals: bool,
+
+    /// How many other image servers to try an image on when
+    /// the one it's on keeps failing
+    #[arg(long = "failovers", default_value_t = DEFAULT_FAILOVERS)]
+    pub failovers: usize,
+
+    /// Download the latest version of galleries that have newer ones
+    #[arg(long = "follow-newer")]
+    pub follow_newer: bool,
+
+    /// Seconds to pause downloads for once the image limit
+    /// is reached, 0 to give up on the images instead
+    #[arg(long = "quota-pause", default_value_t = DEFAULT_PAUSE)]
+    pub quota_pause: u64,
+
+    /// Check the image limit after each pause, pausing
+    /// again until it's below the limit
+    #[arg(long = "poll-quota")]
+    pub poll_quota: bool,
+
+    #[arg(long = "attempts", default_value_t = DEFAULT_ATTEMPTS)]
+    pub attempts: u32,
+
+    /// Delay before the first retry in milliseconds, doubled on every retry
+    #[arg(long = "backoff", default_value_t = DEFAULT_BACKOFF)]
+    pub backoff: u64,
+
+    #[arg(long = "max-backoff", default_value_t = DEFAULT_MAX_BACKOFF)]
+    pub max_backoff: u64,
+
+    /// Gallery pages requested per second, 0 for no limit
+    #[arg(long = "html-rate", default_value_t = DEFAULT_HTML_RATE)]
+    pub html_rate: f64,
+
+    #[arg(long = "html-burst", default_value_t = DEFAULT_HTML_BURST)]
+    pub html_burst: u32,
+
+    /// Images requested per second, 0 for no limit
+    #[arg(long = "image-rate", default_value_t = DEFAULT_IMAGE_RATE)]
+    pub image_rate: f64,
+
+    #[arg(long = "image-burst", default_value_t = DEFAULT_IMAGE_BURST)]
+    pub image_burst: u32,
+
+    #[arg(short = 'A', long = "user-agent", default_value = DEFAULT_USER_AGENT)]
+    pub user_agent: String,
+
+    /// e.g. `http://127.0.0.1:8080` or `socks5://127.0.0.1:1080`
+    #[arg(short = 'x', long = "proxy")]
+    pub proxy: Option<String>,
+
+    /// In seconds
+    #[arg(long = "connect-timeout", default_value_t = DEFAULT_CONNECT_TIMEOUT)]
+    pub connect_timeout: u64,
+
+    /// In seconds
+    #[arg(long = "read-timeout", default_value_t = DEFAULT_READ_TIMEOUT)]
+    pub read_timeout: u64,
+
+    /// Extra header sent with every request, e.g. `-H "Accept-Language: en"`
+    #[arg(short = 'H', long = "header")]
+    pub headers: Vec<String>,
+
+    #[arg(long = "ca-bundle", value_hint = clap::ValueHint::FilePath)]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Netscape `cookies.txt` to read the session cookies from
+    #[arg(short = 'c', long = "cookies", value_hint = clap::ValueHint::FilePath)]
+    pub cookies: Option<PathBuf>,
+
+    /// Endpoint for the `gdata` metadata api
+    #[cfg(feature = "api")]
+    #[arg(long = "api-url", default_value = crate::api::DEFAULT_API_URL)]
+    pub api_url: String,
+
+    /// Scrape metadata from the gallery page instead of using the api
+    #[cfg(feature = "api")]
+    #[arg(long = "no-api")]
+    pub no_api: bool,
+
+    /// Most galleries to take from each search, tag or uploader page
+    #[arg(short = 'm', long = "max-results")]
+    pub max_results: Option<usize>,
+
+    /// Download the account's favorites that weren't synced before
+    #[arg(short = 'f', long = "sync-favorites")]
+    pub sync_favorites: bool,
+
+    /// Favorites category (0-9) to sync, can be repeated. Syncs all of them if omitted
+    #[arg(long = "favcat", value_parser = clap::value_parser!(u8).range(0..=9))]
+    pub favorite_categories: Vec<u8>,
+
+    #[arg(
+        value_name = "INPUT_FILE",
+        value_hint = clap::ValueHint::FilePath,
+        required_unless_present = "sync_favorites"
+    )]
+    pub links_file: Option<PathBuf>,
 }
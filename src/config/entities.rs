@@ -1,11 +1,35 @@
 #[cfg(feature = "config")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "config")]
+use std::path::PathBuf;
 
 #[cfg(feature = "config")]
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Config {
     pub app: AppConfig,
 
+    #[serde(default)]
+    pub download: DownloadConfig,
+
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(default)]
+    pub auth: AuthConfig,
+
+    #[serde(default)]
+    pub search: SearchConfig,
+
+    #[serde(default)]
+    pub favorites: FavoritesConfig,
+
+    #[serde(default)]
+    pub quota: QuotaConfig,
+
+    #[cfg(feature = "api")]
+    #[serde(default)]
+    pub api: ApiConfig,
+
     // FIXME: This fucking program can't compile
     // without their respective features being
     // turned on.
@@ -39,6 +63,167 @@ impl Default for AppConfig {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct DownloadConfig {
+    pub workers: usize,
+    pub galleries: usize,
+    pub state: PathBuf,
+    pub conflict: crate::conflict::ConflictPolicy,
+    pub output: PathBuf,
+    pub template: String,
+    pub naming: crate::naming::NamingMode,
+    pub originals: bool,
+    pub failovers: usize,
+
+    /// Download the latest version of galleries that have newer ones
+    #[serde(rename = "follow-newer")]
+    pub follow_newer: bool,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            workers: crate::downloader::DEFAULT_WORKERS,
+            galleries: crate::scheduler::DEFAULT_GALLERY_WORKERS,
+            state: PathBuf::from(crate::state::DEFAULT_STATE_FILE),
+            conflict: crate::conflict::ConflictPolicy::default(),
+            output: PathBuf::from(crate::template::DEFAULT_OUTPUT),
+            template: crate::template::DEFAULT_TEMPLATE.to_string(),
+            naming: crate::naming::NamingMode::default(),
+            originals: false,
+            failovers: crate::downloader::DEFAULT_FAILOVERS,
+            follow_newer: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub attempts: u32,
+    pub backoff: u64,
+
+    #[serde(rename = "max-backoff")]
+    pub max_backoff: u64,
+
+    #[serde(rename = "html-rate")]
+    pub html_rate: f64,
+
+    #[serde(rename = "html-burst")]
+    pub html_burst: u32,
+
+    #[serde(rename = "image-rate")]
+    pub image_rate: f64,
+
+    #[serde(rename = "image-burst")]
+    pub image_burst: u32,
+
+    #[serde(rename = "user-agent")]
+    pub user_agent: String,
+
+    pub proxy: Option<String>,
+
+    #[serde(rename = "connect-timeout")]
+    pub connect_timeout: u64,
+
+    #[serde(rename = "read-timeout")]
+    pub read_timeout: u64,
+
+    pub headers: Vec<String>,
+
+    #[serde(rename = "ca-bundle")]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            attempts: crate::retry::DEFAULT_ATTEMPTS,
+            backoff: crate::retry::DEFAULT_BACKOFF,
+            max_backoff: crate::retry::DEFAULT_MAX_BACKOFF,
+            html_rate: crate::ratelimit::DEFAULT_HTML_RATE,
+            html_burst: crate::ratelimit::DEFAULT_HTML_BURST,
+            image_rate: crate::ratelimit::DEFAULT_IMAGE_RATE,
+            image_burst: crate::ratelimit::DEFAULT_IMAGE_BURST,
+            user_agent: crate::http::DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            connect_timeout: crate::http::DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: crate::http::DEFAULT_READ_TIMEOUT,
+            headers: vec![],
+            ca_bundle: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AuthConfig {
+    #[serde(rename = "member-id")]
+    pub member_id: Option<String>,
+
+    #[serde(rename = "pass-hash")]
+    pub pass_hash: Option<String>,
+
+    pub igneous: Option<String>,
+
+    /// Path to a Netscape `cookies.txt`
+    pub cookies: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SearchConfig {
+    #[serde(rename = "max-results")]
+    pub max_results: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct FavoritesConfig {
+    pub sync: bool,
+
+    /// Favorites categories (0-9) to sync, all of them if empty
+    pub categories: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct QuotaConfig {
+    /// Seconds to pause downloads for once the image limit is reached,
+    /// 0 to give up on the images instead
+    pub pause: u64,
+
+    /// Check the limit on `home.php` after each pause, pausing
+    /// again until it's below the limit
+    pub poll: bool,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            pause: crate::quota::DEFAULT_PAUSE,
+            poll: false,
+        }
+    }
+}
+
+#[cfg(all(feature = "config", feature = "api"))]
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    pub url: String,
+}
+
+#[cfg(all(feature = "config", feature = "api"))]
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            url: crate::api::DEFAULT_API_URL.to_string(),
+        }
+    }
+}
+
 #[cfg(all(feature = "config", feature = "aniyomi"))]
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct AniyomiConfig {
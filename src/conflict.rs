@@ -0,0 +1,396 @@
+use std::fmt::Display;
+use std::fs::{self, remove_dir_all, remove_file};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+
+use crate::atomic;
+use crate::gallery::Gallery;
+use crate::naming;
+
+/// What to do with a gallery whose directory or archive
+/// is already there from an earlier download
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ConflictPolicy {
+    /// Leave the existing gallery alone
+    Skip,
+    /// Only download the images that are missing
+    #[default]
+    Update,
+    /// Throw away the existing gallery and download it again
+    Overwrite,
+    /// Download into `<title> (2)`, `<title> (3)` and so on
+    Suffix,
+}
+
+impl Display for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Skip => "skip",
+                Self::Update => "update",
+                Self::Overwrite => "overwrite",
+                Self::Suffix => "suffix",
+            }
+        )
+    }
+}
+
+/// Kept in every gallery's directory, and so in its archive too,
+/// saying which gallery it holds. Galleries that happen to end up
+/// under the same name are told apart by it
+pub const MARKER_FILE: &str = ".ehentai-dl";
+
+/// Who a directory or archive belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    /// There's nothing under the name
+    Nobody,
+    /// The gallery with this gid
+    Gallery(u64),
+    /// It was there before galleries got marked, or
+    /// wasn't made by us, so it can't be told
+    Unknown,
+}
+
+/// Decides under which name `gallery`, which would be called `name`,
+/// is downloaded into `parent`, given that it ends up as the directory
+/// `name` and, when `archive` is set, the zip file `name.zip`. Returns
+/// `None` when it shouldn't be downloaded at all.
+///
+/// Whatever holds another gallery is never touched, the gallery goes
+/// next to it instead, as `name (2)` and so on. The same goes for
+/// overwriting whatever isn't marked as holding any gallery
+pub fn resolve(
+    parent: &Path,
+    name: &str,
+    archive: bool,
+    policy: ConflictPolicy,
+    gallery: &Gallery,
+) -> Result<Option<String>, io::Error> {
+    let mut names = suffixed(name);
+    let (name, owner) = loop {
+        let name = names.next().unwrap();
+
+        match get_owner(parent, &name, archive) {
+            Owner::Gallery(gid) if gid != gallery.gid() => {
+                warn!(
+                    "{:?} holds gallery {}, downloading gallery {} next to it",
+                    name,
+                    gid,
+                    gallery.gid()
+                );
+            }
+            // Only what's known to hold this very gallery is deleted,
+            // the name may just as well be a directory of the user's
+            Owner::Unknown if policy == ConflictPolicy::Overwrite => {
+                warn!(
+                    "{:?} isn't marked as holding gallery {}, downloading it next to it instead",
+                    name,
+                    gallery.gid()
+                );
+            }
+            owner => break (name, owner),
+        }
+    };
+
+    if owner == Owner::Nobody {
+        return Ok(Some(name));
+    }
+
+    info!("{:?} already exists, resolving with {}", name, policy);
+    match policy {
+        ConflictPolicy::Skip => Ok(None),
+        // The images that are there already are picked up by the resume
+        // logic, the rest is downloaded. Archives are only written once
+        // every image made it, so one that's on its own is complete,
+        // unless the gallery has gained pages since
+        ConflictPolicy::Update => {
+            if archive && !parent.join(&name).exists() {
+                let pages = read_archive_entry(&zip_path(parent, &name), naming::MANIFEST_FILE)
+                    .map(|manifest| manifest.lines().skip(1).count());
+
+                if pages == Some(gallery.len()) {
+                    info!("{:?} has every page already", name);
+                    return Ok(None);
+                }
+            }
+
+            Ok(Some(name))
+        }
+        ConflictPolicy::Overwrite => {
+            let dir = parent.join(&name);
+            if dir.is_dir() {
+                remove_dir_all(&dir)?;
+            }
+
+            let zip = zip_path(parent, &name);
+            if archive && zip.is_file() {
+                remove_file(&zip)?;
+            }
+
+            Ok(Some(name))
+        }
+        ConflictPolicy::Suffix => {
+            Ok(names.find(|name| get_owner(parent, name, archive) == Owner::Nobody))
+        }
+    }
+}
+
+/// Marks `dir` as holding `gallery`
+pub fn mark(dir: &Path, gallery: &Gallery) -> Result<PathBuf, io::Error> {
+    let path = dir.join(MARKER_FILE);
+    let mut marker = atomic::create(&path)?;
+
+    writeln!(marker, "{}\t{}", gallery.gid(), gallery.token())?;
+    atomic::persist(marker, &path)?;
+
+    Ok(path)
+}
+
+/// `name`, then `name (2)`, `name (3)`...
+fn suffixed(name: &str) -> impl Iterator<Item = String> + '_ {
+    std::iter::once(name.to_string()).chain((2..).map(move |n| format!("{} ({})", name, n)))
+}
+
+fn get_owner(parent: &Path, name: &str, archive: bool) -> Owner {
+    let dir = parent.join(name);
+    let zip = zip_path(parent, name);
+
+    let marker = if dir.exists() {
+        fs::read_to_string(dir.join(MARKER_FILE)).ok()
+    } else if archive && zip.exists() {
+        read_archive_entry(&zip, MARKER_FILE)
+    } else {
+        return Owner::Nobody;
+    };
+
+    marker
+        .and_then(|marker| marker.split_whitespace().next()?.parse().ok())
+        .map_or(Owner::Unknown, Owner::Gallery)
+}
+
+fn zip_path(parent: &Path, name: &str) -> PathBuf {
+    parent.join(format!("{}.zip", name))
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "zip")] {
+        fn read_archive_entry(zip: &Path, file_name: &str) -> Option<String> {
+            match crate::zip::read_entry(zip, file_name) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    warn!("Unable to look into {:?}: {}", zip, e);
+                    None
+                }
+            }
+        }
+    } else {
+        fn read_archive_entry(_zip: &Path, _file_name: &str) -> Option<String> {
+            None
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        pub fn get_policy() -> ConflictPolicy {
+            crate::CONFIG.download.conflict
+        }
+    } else if #[cfg(feature = "cli")] {
+        pub fn get_policy() -> ConflictPolicy {
+            crate::ARGS.conflict
+        }
+    } else {
+        pub fn get_policy() -> ConflictPolicy {
+            ConflictPolicy::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAME: &str = "Gallery";
+
+    fn gallery(gid: u64, pages: usize) -> Gallery {
+        let mut gallery = Gallery::new();
+        gallery.set_id(gid, "0a1b2c3d4e".to_string());
+        gallery.set_len(pages);
+
+        gallery
+    }
+
+    /// Makes the directory `name` with a file in it,
+    /// marked as holding `owner` if there is one
+    fn make_dir(parent: &Path, name: &str, owner: Option<&Gallery>) -> PathBuf {
+        let dir = parent.join(name);
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("1.jpg"), "image").unwrap();
+
+        if let Some(owner) = owner {
+            mark(&dir, owner).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn free_names_are_used() {
+        let parent = tempfile::tempdir().unwrap();
+
+        for policy in [ConflictPolicy::Skip, ConflictPolicy::Suffix] {
+            assert_eq!(
+                resolve(parent.path(), NAME, false, policy, &gallery(1, 2)).unwrap(),
+                Some(NAME.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn skip_leaves_the_gallery_alone() {
+        let parent = tempfile::tempdir().unwrap();
+        let gallery = gallery(1, 2);
+        let dir = make_dir(parent.path(), NAME, Some(&gallery));
+
+        let name = resolve(parent.path(), NAME, false, ConflictPolicy::Skip, &gallery).unwrap();
+        assert_eq!(name, None);
+        assert!(dir.join("1.jpg").is_file());
+    }
+
+    #[test]
+    fn update_downloads_into_the_same_directory() {
+        let parent = tempfile::tempdir().unwrap();
+        let gallery = gallery(1, 2);
+        let dir = make_dir(parent.path(), NAME, Some(&gallery));
+
+        let name = resolve(parent.path(), NAME, false, ConflictPolicy::Update, &gallery).unwrap();
+        assert_eq!(name, Some(NAME.to_string()));
+        assert!(dir.join("1.jpg").is_file());
+    }
+
+    #[test]
+    fn overwrite_deletes_the_gallery() {
+        let parent = tempfile::tempdir().unwrap();
+        let gallery = gallery(1, 2);
+        let dir = make_dir(parent.path(), NAME, Some(&gallery));
+
+        let name = resolve(
+            parent.path(),
+            NAME,
+            false,
+            ConflictPolicy::Overwrite,
+            &gallery,
+        )
+        .unwrap();
+        assert_eq!(name, Some(NAME.to_string()));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn overwrite_leaves_unmarked_directories_alone() {
+        let parent = tempfile::tempdir().unwrap();
+        let gallery = gallery(1, 2);
+        let dir = make_dir(parent.path(), NAME, None);
+
+        let name = resolve(
+            parent.path(),
+            NAME,
+            false,
+            ConflictPolicy::Overwrite,
+            &gallery,
+        )
+        .unwrap();
+        assert_eq!(name, Some(format!("{} (2)", NAME)));
+        assert!(dir.join("1.jpg").is_file());
+
+        // A copy of its own next to it is overwritten though
+        let copy = make_dir(parent.path(), &format!("{} (2)", NAME), Some(&gallery));
+        let name = resolve(
+            parent.path(),
+            NAME,
+            false,
+            ConflictPolicy::Overwrite,
+            &gallery,
+        )
+        .unwrap();
+        assert_eq!(name, Some(format!("{} (2)", NAME)));
+        assert!(dir.join("1.jpg").is_file());
+        assert!(!copy.exists());
+    }
+
+    #[test]
+    fn suffix_downloads_next_to_the_gallery() {
+        let parent = tempfile::tempdir().unwrap();
+        let gallery = gallery(1, 2);
+        make_dir(parent.path(), NAME, Some(&gallery));
+
+        let name = resolve(parent.path(), NAME, false, ConflictPolicy::Suffix, &gallery).unwrap();
+        assert_eq!(name, Some(format!("{} (2)", NAME)));
+
+        make_dir(parent.path(), &format!("{} (2)", NAME), Some(&gallery));
+        let name = resolve(parent.path(), NAME, false, ConflictPolicy::Suffix, &gallery).unwrap();
+        assert_eq!(name, Some(format!("{} (3)", NAME)));
+    }
+
+    #[test]
+    fn other_galleries_are_never_touched() {
+        let parent = tempfile::tempdir().unwrap();
+        let dir = make_dir(parent.path(), NAME, Some(&gallery(2, 2)));
+
+        for policy in [
+            ConflictPolicy::Skip,
+            ConflictPolicy::Update,
+            ConflictPolicy::Overwrite,
+            ConflictPolicy::Suffix,
+        ] {
+            let name = resolve(parent.path(), NAME, false, policy, &gallery(1, 2)).unwrap();
+            assert_eq!(name, Some(format!("{} (2)", NAME)), "{}", policy);
+            assert!(dir.join("1.jpg").is_file());
+        }
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn update_skips_complete_archives() {
+        let parent = tempfile::tempdir().unwrap();
+        let complete = gallery(1, 2);
+
+        // Archived the way the downloader does it,
+        // and without the directory it was made from
+        let staging = tempfile::tempdir().unwrap();
+        let dir = make_dir(staging.path(), NAME, Some(&complete));
+        let manifest = dir.join(naming::MANIFEST_FILE);
+        fs::write(&manifest, "page\tfile\n1\t1.jpg\n2\t2.jpg\n").unwrap();
+
+        let mut archive = crate::zip::make_zip(&zip_path(parent.path(), NAME)).unwrap();
+        for file in [dir.join("1.jpg"), manifest, dir.join(MARKER_FILE)] {
+            crate::zip::add_file::<PathBuf, 1024>(&mut archive, &file, staging.path()).unwrap();
+        }
+        archive.finish().unwrap();
+
+        let name = resolve(parent.path(), NAME, true, ConflictPolicy::Update, &complete).unwrap();
+        assert_eq!(name, None);
+
+        // The gallery has gained a page since
+        let grown = gallery(1, 3);
+        let name = resolve(parent.path(), NAME, true, ConflictPolicy::Update, &grown).unwrap();
+        assert_eq!(name, Some(NAME.to_string()));
+
+        let other = gallery(2, 2);
+        let name = resolve(parent.path(), NAME, true, ConflictPolicy::Update, &other).unwrap();
+        assert_eq!(name, Some(format!("{} (2)", NAME)));
+    }
+}
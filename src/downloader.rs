@@ -7,17 +7,35 @@ cfg_if::cfg_if! {
     }
 }
 
+use std::collections::HashSet;
 use std::fmt::Display;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::Duration;
 
-use futures_util::StreamExt;
+use futures_util::{stream, StreamExt};
 use indicatif::ProgressStyle;
-use log::{debug, info};
-use reqwest::get;
-
+use log::{debug, error, info, warn};
+use reqwest::header::{CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::{Response, StatusCode};
+use sha1_smol::Sha1;
+use tokio::join;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Instant};
+
+use crate::atomic;
+use crate::conflict;
+use crate::extractor::{self, ExtractionError};
 use crate::gallery::{Gallery, Image};
+use crate::http;
+use crate::naming;
 use crate::progress::Progress;
+use crate::quota;
+use crate::retry::{self, is_retryable_request, Retryable};
+use crate::state::{self, ImageStatus};
+use crate::template::{self, TemplateError};
 
 #[cfg(feature = "zip")]
 use crate::zip;
@@ -28,13 +46,19 @@ use std::fs::remove_dir_all;
 const PROGBAR_STYLE: &str = "{prefix:<50} [{bar:>50}] {msg} {bytes}/{total_bytes}";
 const TITLE_DISPLAY_LENGTH: usize = 16;
 
+/// Images of a gallery downloaded at the same time
+pub const DEFAULT_WORKERS: usize = 4;
+/// How many times an image is tried on another image server
+/// after the one it was on kept failing
+pub const DEFAULT_FAILOVERS: usize = 2;
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "metrics")] {
         type DownloadedImage = (usize, PathBuf);
-        type DownloadResponse = Vec<usize>;
+        pub type DownloadResponse = Vec<usize>;
     } else {
         type DownloadedImage = PathBuf;
-        type DownloadResponse = ();
+        pub type DownloadResponse = ();
     }
 }
 
@@ -43,8 +67,16 @@ pub enum DownloadError {
     NetworkError(reqwest::Error),
     FileSystemError(std::io::Error),
     ChunkError(reqwest::Error),
+    StallError(Duration),
+    ExtractionError(ExtractionError<'static>),
     WriteError(std::io::Error),
     AddDirError(std::io::Error),
+    SizeMismatch(PathBuf, u64, u64),
+    HashMismatch(PathBuf, String, String),
+    NotAnImage(String, String),
+    QuotaExceeded(String),
+    ImagesFailed(Vec<(String, DownloadError)>),
+    TemplateError(TemplateError),
 
     #[cfg(feature = "zip")]
     ZipError(zip::ZipError),
@@ -62,8 +94,34 @@ impl Display for DownloadError {
                 Self::NetworkError(e) => format!("performing request: {}", e),
                 Self::FileSystemError(e) => format!("reading/writing to the filesystem: {}", e),
                 Self::ChunkError(e) => format!("awaiting next chunk: {}", e),
+                Self::ExtractionError(e) => format!("extracting image: {}", e),
+                Self::StallError(d) => format!("awaiting next chunk: nothing received for {:?}", d),
                 Self::WriteError(e) => format!("writing to file: {}", e),
                 Self::AddDirError(e) => format!("while creating directory: {}", e),
+                Self::SizeMismatch(p, expected, got) => format!(
+                    "validating {:?}: expected {} bytes, got {}",
+                    p, expected, got
+                ),
+                Self::HashMismatch(p, expected, got) => format!(
+                    "verifying {:?}: expected a SHA-1 starting with {}, got {}",
+                    p, expected, got
+                ),
+                Self::NotAnImage(url, content_type) => format!(
+                    "downloading {:?}: expected an image, got {:?}",
+                    url, content_type
+                ),
+                Self::QuotaExceeded(url) => {
+                    format!("downloading {:?}: the image limit was reached", url)
+                }
+                Self::ImagesFailed(errs) => format!(
+                    "downloading {} image(s): {}",
+                    errs.len(),
+                    errs.iter()
+                        .map(|(file, e)| format!("{:?} ({})", file, e))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ),
+                Self::TemplateError(e) => format!("naming gallery: {}", e),
 
                 #[cfg(feature = "zip")]
                 Self::ZipError(e) => format!("zipping content: {}", e),
@@ -74,40 +132,281 @@ impl Display for DownloadError {
     }
 }
 
+impl DownloadError {
+    /// Whether the error is the image server's doing,
+    /// rather than something on our end
+    fn is_server_error(&self) -> bool {
+        !matches!(
+            self,
+            Self::FileSystemError(_)
+                | Self::WriteError(_)
+                | Self::AddDirError(_)
+                // Every server is going to say the same
+                | Self::QuotaExceeded(_)
+        )
+    }
+}
+
+impl Retryable for DownloadError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::NetworkError(e) | Self::ChunkError(e) => is_retryable_request(e),
+            // A short body is usually a dropped connection, and
+            // the retry will resume from where it stopped
+            Self::SizeMismatch(..) | Self::StallError(_) => true,
+            // The corrupt file is gone by the time this is returned,
+            // so a retry downloads the image from scratch
+            Self::HashMismatch(..) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Downloads `image`, switching to another image server up to the
+/// configured amount of times when the one it's on keeps failing,
+/// and waiting out the image limit whenever it's reached
 async fn download_image(
     image: &Image,
     parent_dir: &PathBuf,
     m_prog: &Progress,
 ) -> Result<DownloadedImage, DownloadError> {
-    let resp = get(image.get_url())
-        .await
-        .map_err(|e| DownloadError::NetworkError(e))?;
+    let mut image = image.clone();
+    let mut failovers = get_failovers();
+    let mut pauses = quota::MAX_PAUSES;
+
+    // Pages hand out the limit notice for as long as the limit is
+    // reached, which may well be over by the time the image is up
+    if quota::is_limit_image(image.get_url()) {
+        quota::ready().await;
+        extractor::refresh_image(&mut image)
+            .await
+            .map_err(DownloadError::ExtractionError)?;
+    }
 
-    let content_length = resp.content_length().unwrap();
-    let mut stream = resp.bytes_stream();
+    loop {
+        quota::ready().await;
+        let since = Instant::now();
+
+        let download = retry::get_policy()
+            .run(image.get_filename(), || {
+                try_download_image(&image, parent_dir, m_prog)
+            })
+            .await;
+
+        let err = match download {
+            Err(DownloadError::QuotaExceeded(url)) => {
+                if pauses == 0 {
+                    warn!(
+                        "{:?} ran into the image limit {} times, giving up on it",
+                        image.get_filename(),
+                        quota::MAX_PAUSES + 1
+                    );
+                    return Err(DownloadError::QuotaExceeded(url));
+                }
+                if !quota::wait_for_reset(since).await {
+                    return Err(DownloadError::QuotaExceeded(url));
+                }
+                pauses -= 1;
+
+                // The page handed out the limit notice in place
+                // of the image, so it has to be asked again
+                if quota::is_limit_image(image.get_url()) {
+                    extractor::refresh_image(&mut image)
+                        .await
+                        .map_err(DownloadError::ExtractionError)?;
+                }
+                continue;
+            }
+            Err(err) if failovers > 0 && err.is_server_error() => err,
+            download => return download,
+        };
+
+        match extractor::reload_image(&mut image).await {
+            Ok(true) => {
+                failovers -= 1;
+                warn!(
+                    "{}, trying {:?} from another server",
+                    err,
+                    image.get_filename()
+                );
+            }
+            Ok(false) => return Err(err),
+            Err(e) => {
+                warn!(
+                    "Unable to find another server for {:?}: {}",
+                    image.get_filename(),
+                    e
+                );
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// Downloads `image`, or its fallback if it has one and `image`
+/// itself can't be downloaded. Returns whichever of the two ended
+/// up on disk
+async fn download_image_or_fallback(
+    mut image: Image,
+    parent_dir: &PathBuf,
+    m_prog: &Progress,
+) -> (Image, Result<DownloadedImage, DownloadError>) {
+    let download = download_image(&image, parent_dir, m_prog).await;
+
+    match (download, image.take_fallback()) {
+        (Err(err), Some(fallback)) => {
+            warn!(
+                "Unable to download the original of {:?}, falling back to the resampled image: {}",
+                image.get_filename(),
+                err
+            );
+
+            // Whatever part of the original made it can't be resumed from
+            let part_path = atomic::part_path(&parent_dir.join(image.get_filename()));
+            if part_path.is_file() {
+                if let Err(e) = fs::remove_file(&part_path) {
+                    return (fallback, Err(DownloadError::FileSystemError(e)));
+                }
+            }
+
+            let download = download_image(&fallback, parent_dir, m_prog).await;
+            (fallback, download)
+        }
+        (download, _) => (image, download),
+    }
+}
+
+async fn try_download_image(
+    image: &Image,
+    parent_dir: &Path,
+    m_prog: &Progress,
+) -> Result<DownloadedImage, DownloadError> {
+    if quota::is_limit_image(image.get_url()) {
+        return Err(DownloadError::QuotaExceeded(image.get_url().clone()));
+    }
 
     let save_path = parent_dir.join(image.get_filename());
+    let part_path = atomic::part_path(&save_path);
+
+    // An image that's already in place may still be one an older
+    // version wrote directly, so it's checked like any other part
+    if save_path.is_file() && !part_path.exists() {
+        fs::rename(&save_path, &part_path).map_err(DownloadError::FileSystemError)?;
+    }
+
+    // Whatever is already on disk is most likely what's left
+    // of an interrupted run, so we only ask for the rest
+    let mut existing = part_path.metadata().map(|m| m.len()).unwrap_or(0);
+    let resp = loop {
+        let mut request = http::client().get(image.get_url());
+        if existing > 0 {
+            request = request.header(RANGE, format!("bytes={}-", existing));
+        }
+
+        // A server that takes the connection but never answers
+        // is as stuck as one that stops halfway through the image
+        let resp = http::send_image(request)
+            .await
+            .map_err(|_| DownloadError::StallError(http::read_timeout()))?
+            .map_err(DownloadError::NetworkError)?;
+
+        if existing == 0 || resp.status() != StatusCode::RANGE_NOT_SATISFIABLE {
+            break resp;
+        }
+
+        // There's nothing after the end of the image, but the part
+        // is only complete if it ends right where the image does
+        let total = range_total(&resp);
+        if total == Some(existing) {
+            info!("{:?} is already complete, skipping", save_path);
+            finish(image, &part_path, &save_path)?;
+            return Ok(downloaded_image(existing as usize, save_path));
+        }
+
+        match total {
+            Some(total) => info!(
+                "{:?} is {} bytes, but the image is {}, downloading it again",
+                part_path, existing, total
+            ),
+            None => info!(
+                "Unable to tell whether {:?} is complete, downloading it again",
+                part_path
+            ),
+        }
+        fs::remove_file(&part_path).map_err(DownloadError::FileSystemError)?;
+        existing = 0;
+    };
+
+    // Image servers either say so outright or redirect to the notice
+    if resp.status().as_u16() == 509 || quota::is_limit_image(resp.url().as_str()) {
+        return Err(DownloadError::QuotaExceeded(image.get_url().clone()));
+    }
+
+    let resp = resp
+        .error_for_status()
+        .map_err(DownloadError::NetworkError)?;
+
+    // Instead of an image, originals can turn out to be a page
+    // saying there isn't enough GP left to download them
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("text/") {
+        return Err(DownloadError::NotAnImage(
+            image.get_url().clone(),
+            content_type.to_string(),
+        ));
+    }
+
+    let resumed = existing > 0 && resumes_at(&resp, existing);
+    let offset = if resumed {
+        info!("Resuming {:?} from byte {}", save_path, existing);
+        existing
+    } else {
+        if existing > 0 {
+            info!(
+                "Server ignored the range for {:?}, downloading it again",
+                save_path
+            );
+        }
+        0
+    };
+
+    let expected = resp.content_length().map(|len| len + offset);
+    let mut stream = resp.bytes_stream();
+
     let mut file = OpenOptions::new()
-        .create_new(true)
+        .create(true)
         .write(true)
-        .open(&save_path)
-        .map_err(|e| DownloadError::FileSystemError(e))?;
-    let mut downloaded = 0;
-
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part_path)
+        .map_err(DownloadError::FileSystemError)?;
+    let mut downloaded = offset as usize;
+
+    // Image servers don't always say how big the image is, but
+    // the image page does, close enough for a progress bar
     let download_prog = m_prog.add_custom_prog(
-        content_length,
+        expected.or(image.get_declared_size()).unwrap_or(0),
         format!("Downloading {}", try_truncate(image.get_filename())),
         ProgressStyle::with_template(PROGBAR_STYLE).unwrap(),
     );
+    download_prog.inc(offset);
 
-    while let Some(chunk) = stream.next().await {
-        let chunk = chunk.map_err(|e| DownloadError::ChunkError(e))?;
+    let read_timeout = http::read_timeout();
+    while let Some(chunk) = timeout(read_timeout, stream.next())
+        .await
+        .map_err(|_| DownloadError::StallError(read_timeout))?
+    {
+        let chunk = chunk.map_err(DownloadError::ChunkError)?;
         downloaded += chunk.len();
 
-        file.write(&chunk)
-            .map_err(|e| DownloadError::WriteError(e))?;
+        file.write_all(&chunk).map_err(DownloadError::WriteError)?;
         download_prog.inc(chunk.len() as u64);
     }
+    download_prog.finish_and_clear();
 
     debug!(
         "Written {} bytes total to {}",
@@ -115,27 +414,155 @@ async fn download_image(
         save_path.file_name().unwrap().to_str().unwrap()
     );
 
+    if let Some(expected) = expected {
+        if downloaded as u64 != expected {
+            return Err(DownloadError::SizeMismatch(
+                save_path,
+                expected,
+                downloaded as u64,
+            ));
+        }
+    }
+
+    if let Some(declared) = image.get_declared_size() {
+        if !matches_declared(declared, downloaded as u64) {
+            warn!(
+                "{:?} is {} bytes, but its page says it's about {}",
+                save_path, downloaded, declared
+            );
+        }
+    }
+
+    finish(image, &part_path, &save_path)?;
+    Ok(downloaded_image(downloaded, save_path))
+}
+
+/// Moves the fully downloaded `part_path` to `save_path`,
+/// as long as it is what the image page says it is
+fn finish(image: &Image, part_path: &Path, save_path: &Path) -> Result<(), DownloadError> {
+    verify(image, part_path)?;
+
+    fs::rename(part_path, save_path).map_err(DownloadError::FileSystemError)
+}
+
+/// Checks `path` against the hash in its image page's url, removing
+/// it if it doesn't match so that it's downloaded again from scratch
+fn verify(image: &Image, path: &Path) -> Result<(), DownloadError> {
+    let Some(expected) = image.get_expected_hash() else {
+        return Ok(());
+    };
+
+    let mut file = File::open(path).map_err(DownloadError::FileSystemError)?;
+    let mut hasher = Sha1::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .map_err(DownloadError::FileSystemError)?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..read]);
+    }
+
+    let hash = hasher.digest().to_string();
+    if hash.starts_with(expected.as_str()) {
+        debug!("{:?} matches its hash {}", path, expected);
+        return Ok(());
+    }
+
+    fs::remove_file(path).map_err(DownloadError::FileSystemError)?;
+    Err(DownloadError::HashMismatch(
+        path.to_path_buf(),
+        expected.clone(),
+        hash[..expected.len()].to_string(),
+    ))
+}
+
+/// Image pages round sizes to a few digits ("300.5 KiB",
+/// "1.23 MiB"), so we only expect them to be roughly right
+fn matches_declared(declared: u64, downloaded: u64) -> bool {
+    declared.abs_diff(downloaded) <= 1024 + declared / 200
+}
+
+/// Checks whether `resp` is a partial response that picks up
+/// exactly where our `existing` bytes left off
+fn resumes_at(resp: &Response, existing: u64) -> bool {
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        return false;
+    }
+
+    // Content-Range: bytes <start>-<end>/<total>
+    resp.headers()
+        .get(CONTENT_RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes "))
+        .and_then(|range| range.split('-').next())
+        .and_then(|start| start.parse::<u64>().ok())
+        == Some(existing)
+}
+
+/// The size of the image a 416 says the range is past the end
+/// of, as in `Content-Range: bytes */<total>`
+fn range_total(resp: &Response) -> Option<u64> {
+    resp.headers()
+        .get(CONTENT_RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes */"))
+        .and_then(|total| total.parse::<u64>().ok())
+}
+
+#[allow(unused_variables)]
+fn downloaded_image(size: usize, path: PathBuf) -> DownloadedImage {
     cfg_if::cfg_if! {
         if #[cfg(feature = "metrics")] {
-            Ok((downloaded, save_path))
+            (size, path)
         } else {
-            Ok(save_path)
+            path
         }
     }
 }
 
+/// Creates `path` unless it's already there, so that galleries
+/// interrupted mid-download can be picked up again
+fn ensure_dir(path: &PathBuf) -> Result<(), std::io::Error> {
+    if path.is_dir() {
+        return Ok(());
+    }
+
+    create_dir(path)
+}
+
 pub async fn download_gallery<const CHUNK_SIZE: usize>(
-    gallery: &Gallery,
+    gallery: &mut Gallery,
     m_prog: &Progress,
 ) -> Result<DownloadResponse, DownloadError> {
-    let cwd = PathBuf::from(".");
-    let root_dir = if cfg!(feature = "aniyomi") {
-        let cwd = cwd.join(gallery.title());
-        create_dir(&cwd).map_err(|e| DownloadError::AddDirError(e))?;
+    let path = template::get_output().join(
+        template::render(&template::get_template(), gallery)
+            .map_err(DownloadError::TemplateError)?,
+    );
+    let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+
+    let name = conflict::resolve(&parent, &name, use_zip(), conflict::get_policy(), gallery)
+        .map_err(DownloadError::FileSystemError)?;
+    let Some(name) = name else {
+        info!("Skipping {:?}, it was downloaded before", gallery.title());
+        return Ok(DownloadResponse::default());
+    };
 
-        cwd.join("OneShot")
+    fs::create_dir_all(&parent).map_err(DownloadError::AddDirError)?;
+    let gallery_dir = parent.join(&name);
+    ensure_dir(&gallery_dir).map_err(DownloadError::AddDirError)?;
+    // Claimed right away, so that a gallery running alongside
+    // this one that's named the same goes somewhere else
+    let marker = conflict::mark(&gallery_dir, gallery).map_err(DownloadError::WriteError)?;
+
+    let root_dir = if cfg!(feature = "aniyomi") {
+        gallery_dir.join("OneShot")
     } else {
-        cwd.join(gallery.title())
+        gallery_dir.clone()
     };
 
     info!("Current Dir: {:?}", root_dir);
@@ -152,15 +579,121 @@ pub async fn download_gallery<const CHUNK_SIZE: usize>(
     let mut dl_sizes = vec![];
     let mut dl_files = vec![];
 
-    create_dir(&root_dir).map_err(|e| DownloadError::FileSystemError(e))?;
+    ensure_dir(&root_dir).map_err(DownloadError::FileSystemError)?;
+
+    let extract_prog = m_prog.add_prog(gallery.len() as u64, "Extracting image data");
+    let workers = get_workers();
+
+    // Image pages are resolved one by one and handed to the download
+    // workers as soon as they are, instead of resolving the whole
+    // gallery first. The channel being bounded keeps extraction from
+    // running too far ahead of the downloads
+    let (image_tx, mut image_rx) = mpsc::channel(workers * 2);
+
+    // Images the journal says are done, and that are still where they
+    // were left, don't have to be looked up or downloaded again
+    let state = state::get_state();
+    let gid = gallery.gid();
+    let mut known = state.downloaded_images(gid);
+    known.retain(|_, image| root_dir.join(image.get_filename()).is_file());
+    if !known.is_empty() {
+        info!("{} image(s) were downloaded already", known.len());
+    }
+
+    let done = known.keys().copied().collect::<HashSet<_>>();
+    let images = extractor::get_images(gallery, naming::get_mode(), known);
+
+    let producer = async move {
+        let mut images = pin!(images);
+        let mut index = 0;
+
+        while let Some(image) = images.next().await {
+            extract_prog.inc(1);
+            if image_tx.send((index, image)).await.is_err() {
+                break;
+            }
+
+            index += 1;
+        }
+        extract_prog.finish_and_clear();
+    };
+
+    let parent_dir = &root_dir;
+    let done = &done;
+    let consumer = stream::poll_fn(|cx| image_rx.poll_recv(cx))
+        .map(|(index, image)| async move {
+            match image {
+                Ok(image) if done.contains(&index) => {
+                    let path = parent_dir.join(image.get_filename());
+                    let download = fs::metadata(&path)
+                        .map(|meta| downloaded_image(meta.len() as usize, path))
+                        .map_err(DownloadError::FileSystemError);
+                    (index, Ok((image, download)))
+                }
+                Ok(image) => {
+                    let (image, download) =
+                        download_image_or_fallback(image, parent_dir, m_prog).await;
+                    let status = match download {
+                        Ok(_) => ImageStatus::Downloaded,
+                        Err(_) => ImageStatus::Failed,
+                    };
+                    state.set_image(gid, index, status, &image);
+
+                    (index, Ok((image, download)))
+                }
+                Err(err) => (index, Err(err)),
+            }
+        })
+        .buffer_unordered(workers)
+        .inspect(|_| download_prog.inc(1))
+        .collect::<Vec<_>>();
+
+    let (_, mut downloads) = join!(producer, consumer);
+
+    // Downloads finish in whatever order they please, but `dl_files`
+    // has to be in page order
+    downloads.sort_by_key(|(index, _)| *index);
+
+    let mut failed = vec![];
+    for (index, extracted) in downloads {
+        let (image, download) = match extracted {
+            Ok(extracted) => extracted,
+            Err(err) => {
+                error!("Error while extracting page {}: {}", index + 1, err);
+                failed.push((
+                    format!("page {}", index + 1),
+                    DownloadError::ExtractionError(err),
+                ));
+
+                continue;
+            }
+        };
 
-    for image in gallery.images() {
-        let (dl_size, dl_path) = download_image(image, &root_dir, &m_prog).await?;
+        match download {
+            Ok((dl_size, dl_path)) => {
+                #[cfg(feature = "metrics")]
+                dl_sizes.push(dl_size);
+                dl_files.push(dl_path);
+            }
+            Err(err) => {
+                error!(
+                    "Error while downloading image {:?}: {}",
+                    image.get_filename(),
+                    err
+                );
+                failed.push((image.get_filename().clone(), err));
+            }
+        }
+
+        gallery.add_image(image);
+    }
 
-        #[cfg(feature = "metrics")]
-        dl_sizes.push(dl_size);
-        dl_files.push(dl_path);
-        download_prog.inc(1);
+    // Packaging a gallery with missing pages would only produce
+    // a broken archive, so we stop here and let the caller know
+    // which images didn't make it
+    if !failed.is_empty() {
+        download_prog.finish_and_clear();
+        return Err(DownloadError::ImagesFailed(failed));
     }
 
     // cfg! only evaluates to true or false,
@@ -182,17 +715,14 @@ pub async fn download_gallery<const CHUNK_SIZE: usize>(
 
             if use_aniyomi { // This *sorta* evaluates on runtime
                 download_prog.set_message("Finishing Touches");
-                let meta = AniyomiMeta::from(gallery);
+                let meta = AniyomiMeta::from(&*gallery);
                 let meta_path = root_dir.with_file_name("details.json");
 
-                let mut meta_file = OpenOptions::new()
-                    .create_new(true)
-                    .write(true)
-                    .open(&meta_path)
-                    .map_err(|e| DownloadError::FileSystemError(e))?;
+                let mut meta_file = atomic::create(&meta_path).map_err(|e| DownloadError::FileSystemError(e))?;
 
                 info!("Writing aniyomi meta to {:?}", &meta_path);
                 to_json_file(&mut meta_file, &meta).map_err(|e| DownloadError::WriteError(e))?;
+                atomic::persist(meta_file, &meta_path).map_err(DownloadError::WriteError)?;
                 let cover_file = make_cover(dl_files.get(0).unwrap()).map_err(|e| DownloadError::WriteError(e))?;
 
                 dl_files.push(meta_path);
@@ -201,13 +731,13 @@ pub async fn download_gallery<const CHUNK_SIZE: usize>(
         }
     }
 
+    let manifest =
+        naming::write_manifest(gallery, &gallery_dir).map_err(DownloadError::WriteError)?;
+    dl_files.push(manifest);
+    dl_files.push(marker);
+
     cfg_if::cfg_if! {
         if #[cfg(feature = "zip")] {
-            #[cfg(feature = "config")]
-            let use_zip = crate::CONFIG.app.features.contains(&String::from("zip"));
-            #[cfg(not(feature = "config"))]
-            let use_zip = true;
-
             #[cfg(feature = "config")]
             let zip_delete_orig = crate::CONFIG.zip.delete_original;
             #[cfg(feature = "cli")]
@@ -215,28 +745,28 @@ pub async fn download_gallery<const CHUNK_SIZE: usize>(
             #[cfg(not(any(feature = "config", feature = "cli")))]
             let zip_delete_orig = false;
 
-            if use_zip {
+            if use_zip() {
                 let zip_prog = m_prog.add_prog(dl_files.len() as u64 + 1, format!("Zipping Gallery {:?}", gallery.title()));
-                let mut zip_file = zip::make_zip(&format!("{}.zip", gallery.title())).map_err(|e| DownloadError::ZipError(e))?;
+                let mut zip_file = zip::make_zip(&parent.join(format!("{}.zip", name))).map_err(|e| DownloadError::ZipError(e))?;
 
                 let rd_prog = m_prog.add_prog(1, "Root directory");
-                zip::add_file::<PathBuf, CHUNK_SIZE>(&mut zip_file, &root_dir).map_err(|e| DownloadError::ZipError(e))?;
+                zip::add_file::<PathBuf, CHUNK_SIZE>(&mut zip_file, &root_dir, &parent).map_err(|e| DownloadError::ZipError(e))?;
                 rd_prog.finish_and_clear();
 
                 for file in dl_files {
                     // why. just why
                     // what was i trying to achieve by
                     // passing in `&root_dir` in the previous commits
-                    let written = zip::add_file::<PathBuf, CHUNK_SIZE>(&mut zip_file, &file).map_err(|e| DownloadError::ZipError(e))?;
+                    let written = zip::add_file::<PathBuf, CHUNK_SIZE>(&mut zip_file, &file, &parent).map_err(|e| DownloadError::ZipError(e))?;
 
                     info!("Written file {:?} to disc ({} bytes written)", file.to_str().unwrap(), written);
                     zip_prog.inc(1);
                 }
+                zip_file.finish().map_err(DownloadError::ZipError)?;
                 zip_prog.finish_and_clear();
 
                 if zip_delete_orig {
-                    let root = cwd.join(gallery.title());
-                    remove_dir_all(&root).map_err(|e| DownloadError::RemoveDirError(root, e))?;
+                    remove_dir_all(&gallery_dir).map_err(|e| DownloadError::RemoveDirError(gallery_dir, e))?;
                 }
             }
         }
@@ -251,6 +781,50 @@ pub async fn download_gallery<const CHUNK_SIZE: usize>(
     }
 }
 
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        fn get_workers() -> usize {
+            crate::CONFIG.download.workers.max(1)
+        }
+    } else if #[cfg(feature = "cli")] {
+        fn get_workers() -> usize {
+            crate::ARGS.workers.max(1)
+        }
+    } else {
+        fn get_workers() -> usize {
+            DEFAULT_WORKERS
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        fn get_failovers() -> usize {
+            crate::CONFIG.download.failovers
+        }
+    } else if #[cfg(feature = "cli")] {
+        fn get_failovers() -> usize {
+            crate::ARGS.failovers
+        }
+    } else {
+        fn get_failovers() -> usize {
+            DEFAULT_FAILOVERS
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "zip", feature = "config"))] {
+        fn use_zip() -> bool {
+            crate::CONFIG.app.features.contains(&String::from("zip"))
+        }
+    } else {
+        fn use_zip() -> bool {
+            cfg!(feature = "zip")
+        }
+    }
+}
+
 fn try_truncate(raw: &String) -> String {
     let mut raw = raw.clone();
 
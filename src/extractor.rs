@@ -1,14 +1,31 @@
 use std::{
+    collections::{HashMap, HashSet, VecDeque},
     fmt::{Debug, Display},
+    path::Path,
     string,
 };
 
-use log::{debug, info};
-use reqwest::{get, IntoUrl};
-use scraper::Html;
-
+use futures_util::{stream, Stream};
+use log::{debug, info, warn};
+use reqwest::{IntoUrl, StatusCode};
+use scraper::{ElementRef, Html};
+
+#[cfg(feature = "api")]
+use crate::api;
+use crate::naming::NamingMode;
+use crate::parser::Pagination;
+use crate::ratelimit::Budget;
+use crate::retry::{self, is_retryable_request, Retryable};
+use crate::{auth, http};
 use crate::{gallery, progress::Progress};
 
+/// What the site answers with when there's no gallery at a url
+const GONE_STATUSES: &[StatusCode] = &[StatusCode::NOT_FOUND, StatusCode::GONE];
+
+/// Added to a gallery's url to get past its content warning
+/// for good, as opposed to only for the current session
+const CONTENT_WARNING_QUERY: &str = "nw=always";
+
 #[derive(Debug)]
 pub enum ExtractionError<'a> {
     NetworkError(reqwest::Error),
@@ -17,6 +34,31 @@ pub enum ExtractionError<'a> {
     SelectorParseError(scraper::error::SelectorErrorKind<'a>),
     EmptyData(&'a str),
     DataParseError(crate::parser::ParseError<0>),
+    LoginRequired,
+    ContentWarning(String),
+    Unavailable(String, Unavailable),
+}
+
+/// Why a gallery page shows a notice instead of the gallery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unavailable {
+    Removed,
+    Expunged,
+    KeyMissing,
+}
+
+impl Display for Unavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Removed => "the gallery was removed",
+                Self::Expunged => "the gallery was expunged",
+                Self::KeyMissing => "the key is missing or wrong",
+            }
+        )
+    }
 }
 
 impl<'a> Display for ExtractionError<'a> {
@@ -31,19 +73,85 @@ impl<'a> Display for ExtractionError<'a> {
                 Self::SelectorParseError(e) => format!("parsing selector: {}", e),
                 Self::EmptyData(e) => format!("expecting data: expected {}, got none", e),
                 Self::DataParseError(e) => format!("parsing: {}", e),
+                Self::LoginRequired => {
+                    "accessing ExHentai: no credentials were provided".to_string()
+                }
+                Self::ContentWarning(url) => {
+                    format!("accessing {:?}: the content warning wouldn't go away", url)
+                }
+                Self::Unavailable(url, why) => format!("accessing {:?}: {}", url, why),
             }
         )
     }
 }
 
-async fn get_html<'a, U>(url: U) -> Result<Html, ExtractionError<'a>>
+impl<'a> Retryable for ExtractionError<'a> {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::NetworkError(e) | Self::BytesDecodeError(e) => is_retryable_request(e),
+            _ => false,
+        }
+    }
+}
+
+impl<'a> ExtractionError<'a> {
+    /// What kind of problem this is, for the summary of a run
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::NetworkError(_) | Self::BytesDecodeError(_) => "network",
+            Self::LoginRequired => "login required",
+            Self::ContentWarning(_) => "content warning",
+            Self::Unavailable(_, Unavailable::Removed) => "removed",
+            Self::Unavailable(_, Unavailable::Expunged) => "expunged",
+            Self::Unavailable(_, Unavailable::KeyMissing) => "key missing",
+            _ => "extraction",
+        }
+    }
+}
+
+pub async fn get_html<'a, U>(url: U) -> Result<Html, ExtractionError<'a>>
+where
+    U: IntoUrl + Display + Clone,
+{
+    get_html_keeping(url, &[]).await.map(|(_, html)| html)
+}
+
+/// Like `get_html`, except that pages answered with one of the
+/// statuses in `keep` are returned like any other, along with
+/// their status, rather than as an error
+async fn get_html_keeping<'a, U>(
+    url: U,
+    keep: &[StatusCode],
+) -> Result<(StatusCode, Html), ExtractionError<'a>>
+where
+    U: IntoUrl + Display + Clone,
+{
+    let what = url.to_string();
+
+    retry::get_policy()
+        .run(&what, || fetch_html(url.clone(), keep))
+        .await
+}
+
+async fn fetch_html<'a, U>(
+    url: U,
+    keep: &[StatusCode],
+) -> Result<(StatusCode, Html), ExtractionError<'a>>
 where
     U: IntoUrl + Display + Clone,
 {
-    let resp = get(url.clone())
+    let resp = http::get(url.clone(), Budget::Html)
         .await
-        .map_err(|e| ExtractionError::NetworkError(e))?;
-    debug!("GET {} => {}", url, resp.status());
+        .map_err(ExtractionError::NetworkError)?;
+    let status = resp.status();
+    debug!("GET {} => {}", url, status);
+
+    let resp = if keep.contains(&status) {
+        resp
+    } else {
+        resp.error_for_status()
+            .map_err(ExtractionError::NetworkError)?
+    };
 
     let bytes = resp
         .bytes()
@@ -55,7 +163,87 @@ where
     let body = String::from_utf8(bytes).map_err(|e| ExtractionError::StringDecodeError(e))?;
     let html = Html::parse_fragment(body.as_str());
 
-    Ok(html)
+    Ok((status, html))
+}
+
+/// Gets a page of a gallery, accepting the content warning the site
+/// shows in place of flagged galleries, the way its "View Gallery"
+/// link does
+async fn get_gallery_html<'a>(
+    url: String,
+    keep: &[StatusCode],
+) -> Result<(StatusCode, Html), ExtractionError<'a>> {
+    let (status, html) = get_html_keeping(url.as_str(), keep).await?;
+    if !is_content_warning(&html)? {
+        return Ok((status, html));
+    }
+
+    info!("{:?} is behind a content warning, accepting it", url);
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let warned = format!("{}{}{}", url, separator, CONTENT_WARNING_QUERY);
+    let (status, html) = get_html_keeping(warned, keep).await?;
+    if is_content_warning(&html)? {
+        return Err(ExtractionError::ContentWarning(url));
+    }
+
+    Ok((status, html))
+}
+
+/// Gets the first page of a gallery, telling apart the
+/// notices the site shows when there's no gallery to get
+async fn get_available_html<'a>(url: &str) -> Result<Html, ExtractionError<'a>> {
+    // Notices may come with an error status, and
+    // are only any use if we get to read them
+    let (status, html) = get_gallery_html(url.to_string(), GONE_STATUSES).await?;
+
+    match get_unavailable(&html)? {
+        Some(why) => Err(ExtractionError::Unavailable(url.to_string(), why)),
+        // Whatever the page says, the site has no such gallery
+        None if GONE_STATUSES.contains(&status) => Err(ExtractionError::Unavailable(
+            url.to_string(),
+            Unavailable::Removed,
+        )),
+        None => Ok(html),
+    }
+}
+
+fn get_unavailable<'a>(html: &Html) -> Result<Option<Unavailable>, ExtractionError<'a>> {
+    let title = compile!(selector "h1#gn")?;
+    if html.select(&title).next().is_some() {
+        return Ok(None);
+    }
+
+    let text = html.root_element().text().collect::<String>();
+    Ok(if text.contains("Key missing") {
+        Some(Unavailable::KeyMissing)
+    } else if text.to_lowercase().contains("expunged") {
+        Some(Unavailable::Expunged)
+    } else if text.contains("removed or is unavailable")
+        || text.contains("unavailable due to")
+        || text.contains("Gallery not found")
+    {
+        Some(Unavailable::Removed)
+    } else {
+        None
+    })
+}
+
+/// The latest of the "There are newer versions of this
+/// gallery available" links, which are listed oldest first
+fn get_newer_version<'a>(html: &Html) -> Result<Option<String>, ExtractionError<'a>> {
+    let sel = compile!(selector "div#gnd a")?;
+
+    Ok(html
+        .select(&sel)
+        .filter_map(|link| link.value().attr("href"))
+        .next_back()
+        .map(|href| href.to_string()))
+}
+
+fn is_content_warning<'a>(html: &Html) -> Result<bool, ExtractionError<'a>> {
+    let sel = compile!(selector "a[href*='nw=always'], a[href*='nw=session']")?;
+
+    Ok(html.select(&sel).next().is_some())
 }
 
 pub async fn get_gallery<'a, U>(
@@ -66,26 +254,64 @@ where
     U: Debug + Display + ToString + ?Sized,
     &'a U: IntoUrl + 'a,
 {
+    // ExHentai answers anonymous requests with an empty page,
+    // which would otherwise surface as a confusing `EmptyData`
+    if url.to_string().contains("exhentai.org") && !auth::get_credentials().is_logged_in() {
+        return Err(ExtractionError::LoginRequired);
+    }
+
     let mut gallery = gallery::Gallery::new();
-    let overall_progress = progress.add_prog(3, "Getting info for gallery");
+    let overall_progress = progress.add_prog(2, "Getting info for gallery");
 
     overall_progress.set_message("webpage");
     info!("Extracting info for {:?}", url);
-    let html = get_html(url).await?;
-
-    let pages = get_pages(&html)?;
-    info!("{} page(s) to download", pages);
-
-    overall_progress.set_message("title");
-    get_title(&mut gallery, &html)?;
-    overall_progress.inc(1);
+    let mut url = url.to_string();
+    let mut html = get_available_html(&url).await?;
+
+    if let Some(newer) = get_newer_version(&html)? {
+        if get_follow_newer_setting() {
+            info!(
+                "{:?} has a newer version, downloading {:?} instead",
+                url, newer
+            );
+            html = get_available_html(&newer).await?;
+            url = newer;
+        } else {
+            warn!("{:?} has a newer version at {:?}", url, newer);
+            gallery.set_newer_version(Some(newer));
+        }
+    }
 
-    overall_progress.set_message("tags");
-    get_tags(&mut gallery, &html)?;
+    let (gid, token) =
+        crate::parser::get_gallery_id(&url).map_err(ExtractionError::DataParseError)?;
+    gallery.set_id(gid, token);
+
+    overall_progress.set_message("metadata");
+    let from_api = get_metadata(&mut gallery).await;
+
+    let pagination = get_pagination(&html)?;
+    gallery.set_url(url);
+    gallery.set_len(pagination.total);
+    gallery.set_listing_pages(pagination.pages());
+    info!(
+        "{} image(s) over {} page(s) to download",
+        pagination.total,
+        pagination.pages()
+    );
+
+    // The api already gave us all of these, the page
+    // is only scraped for them when it couldn't
+    if !from_api {
+        overall_progress.set_message("title");
+        get_title(&mut gallery, &html)?;
+        get_category(&mut gallery, &html)?;
+    }
     overall_progress.inc(1);
 
-    overall_progress.set_message("images");
-    get_images(&pages, &url.to_string(), &mut gallery, &progress).await?;
+    if !from_api {
+        overall_progress.set_message("tags");
+        get_tags(&mut gallery, &html)?;
+    }
     overall_progress.inc(1);
 
     overall_progress.set_message("");
@@ -96,6 +322,17 @@ where
 
 fn get_title<'a>(gallery: &mut gallery::Gallery, html: &Html) -> Result<(), ExtractionError<'a>> {
     let sel = compile!(selector "h1#gn")?;
+    let jpn = compile!(selector "h1#gj")?;
+    let uploader = compile!(selector "div#gdn a")?;
+
+    // Same as the category, these only end up in paths
+    // and may well be missing
+    if let Some(title) = html.select(&jpn).next() {
+        gallery.set_title_jpn(title.text().collect::<String>());
+    }
+    if let Some(uploader) = html.select(&uploader).next() {
+        gallery.set_uploader(uploader.text().collect::<String>());
+    }
 
     if let Some(title) = html.select(&sel).next() {
         gallery.set_title(title.text().collect::<String>());
@@ -106,68 +343,223 @@ fn get_title<'a>(gallery: &mut gallery::Gallery, html: &Html) -> Result<(), Extr
     }
 }
 
-fn get_pages<'a>(html: &Html) -> Result<u8, ExtractionError<'a>> {
+/// Fills `gallery` from the `gdata` api, returns whether it did
+#[cfg(feature = "api")]
+async fn get_metadata(gallery: &mut gallery::Gallery) -> bool {
+    if !api::is_enabled() {
+        return false;
+    }
+
+    match api::get_metadata(gallery.gid(), gallery.token()).await {
+        Ok(metadata) => {
+            metadata.apply(gallery);
+            debug!(
+                "Gallery {} was posted at {:?}, has {:?} files ({:?} bytes) and is rated {:?}",
+                gallery.gid(),
+                gallery.posted(),
+                gallery.file_count(),
+                gallery.file_size(),
+                gallery.rating()
+            );
+            true
+        }
+        Err(e) => {
+            warn!("{}, falling back to scraping the gallery page", e);
+            false
+        }
+    }
+}
+
+#[cfg(not(feature = "api"))]
+async fn get_metadata(_gallery: &mut gallery::Gallery) -> bool {
+    false
+}
+
+/// The category isn't essential to downloading a gallery,
+/// so unlike the title it's fine for it to be missing
+fn get_category<'a>(
+    gallery: &mut gallery::Gallery,
+    html: &Html,
+) -> Result<(), ExtractionError<'a>> {
+    let sel = compile!(selector "div#gdc div")?;
+
+    if let Some(category) = html.select(&sel).next() {
+        gallery.set_category(category.text().collect::<String>());
+    }
+
+    Ok(())
+}
+
+fn get_pagination<'a>(html: &Html) -> Result<Pagination, ExtractionError<'a>> {
     let sel = compile!(selector "p.gpc")?;
     let pages_raw = html
         .select(&sel)
         .next()
         .ok_or(ExtractionError::EmptyData("page count"))?;
 
-    let pagination = crate::parser::get_pagination(&pages_raw.text().collect::<String>())
-        .map_err(|e| ExtractionError::DataParseError(e))?;
-
-    // we use .ceil instead of .round because we want even 1.1 to
-    // get considered as 2.0, .ceil achieves this function while
-    // .round, well, rounds it off to 1.0
-    Ok(pagination.ceil() as u8)
+    crate::parser::get_pagination(&pages_raw.text().collect::<String>())
+        .map_err(ExtractionError::DataParseError)
 }
 
-async fn get_images<'a>(
-    pages: &u8,
-    gallery_url: &String,
-    gallery: &mut gallery::Gallery,
-    progress: &Progress,
-) -> Result<(), ExtractionError<'a>> {
-    let sel = compile!(selector "div#gdt div.gdtm div a")?;
-
-    for i in 0..*pages {
-        let url = format!("{}?p={}", gallery_url, i);
-        let html = get_html(url).await?;
-        let images = html.select(&sel).collect::<Vec<scraper::ElementRef>>();
+/// Yields every image of `gallery` in order. Listing pages are
+/// only requested once the images of the previous one are used up,
+/// so a gallery with thousands of pages can be consumed without
+/// extracting all of it first.
+///
+/// Images are named according to `naming`. `known` holds the
+/// images that are already on disk, by index. Their image pages
+/// aren't requested, and they're yielded with only their names
+/// filled in
+pub fn get_images<'a>(
+    gallery: &gallery::Gallery,
+    naming: NamingMode,
+    known: HashMap<usize, gallery::Image>,
+) -> impl Stream<Item = Result<gallery::Image, ExtractionError<'a>>> {
+    let gallery_url = gallery.url().clone();
+    let pages = gallery.listing_pages();
+    let total = gallery.len();
+
+    // Whatever is on disk already keeps its name, so images
+    // that are new to this run are named around them
+    let taken = known
+        .values()
+        .map(|image| image.get_filename().to_lowercase())
+        .collect();
 
-        let prog = progress.add_prog(
-            images.len() as u64,
-            format!("Extracting image data (page {} of {})", i + 1, pages),
-        );
+    let state = ImagesState {
+        page: 0,
+        queue: VecDeque::new(),
+        index: 0,
+        known,
+        taken,
+    };
+
+    stream::unfold(state, move |mut state| {
+        let gallery_url = gallery_url.clone();
+
+        async move {
+            loop {
+                if let Some(url) = state.queue.pop_front() {
+                    let image = match state.known.remove(&state.index) {
+                        Some(mut known) => {
+                            known.set_url(url);
+                            Ok(known)
+                        }
+                        None => {
+                            let mut image = gallery::Image::new(&url);
+                            get_image_data(&mut image).await.map(|_| {
+                                let name =
+                                    naming.name(state.index, total, image.get_original_name());
+                                image.set_filename(state.take(&name));
+                                image
+                            })
+                        }
+                    };
+                    state.index += 1;
+
+                    return Some((image, state));
+                }
+
+                if state.page >= pages {
+                    return None;
+                }
+
+                match get_image_pages(&gallery_url, state.page).await {
+                    Ok(urls) => state.queue.extend(urls),
+                    Err(e) => {
+                        // Nothing after a missing listing page can be trusted
+                        // to be in order, so the error is the last thing this yields
+                        state.page = pages;
+                        state.queue.clear();
+                        return Some((Err(e), state));
+                    }
+                }
+                state.page += 1;
+            }
+        }
+    })
+}
 
-        for image in images {
-            let url = image.value().attr("href").unwrap().to_string();
-            let mut image = gallery::Image::new(&url);
+struct ImagesState {
+    /// The next listing page to request
+    page: usize,
+    /// Image pages from the last listing page that weren't yielded yet
+    queue: VecDeque<String>,
+    /// Index of the next image in the gallery
+    index: usize,
+    /// Images that are already downloaded, by index
+    known: HashMap<usize, gallery::Image>,
+    /// Lowercased names of the images that are already
+    /// downloaded, and of the ones yielded so far
+    taken: HashSet<String>,
+}
 
-            get_image_data(&mut image).await?;
-            gallery.add_image(image);
-            prog.inc(1);
+impl ImagesState {
+    /// Claims `name` for the next image, or if an earlier image has it
+    /// already, the first of `name (2)`, `name (3)`... that's free.
+    /// Names are compared ignoring case, as some filesystems do.
+    ///
+    /// Images that are already downloaded have their names claimed
+    /// up front, so an image extracted in a later run can't end up
+    /// with the name of one that was restored from the journal
+    fn take(&mut self, name: &str) -> String {
+        if self.taken.insert(name.to_lowercase()) {
+            return name.to_string();
         }
-        prog.finish_and_clear();
+
+        let path = Path::new(name);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = path
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy()))
+            .unwrap_or_default();
+
+        let name = (2..)
+            .map(|n| format!("{} ({}){}", stem, n, ext))
+            .find(|name| !self.taken.contains(&name.to_lowercase()))
+            .unwrap();
+        debug!("Renaming duplicate image {:?} to {:?}", path, name);
+
+        self.taken.insert(name.to_lowercase());
+        name
     }
+}
 
-    Ok(())
+/// The image pages linked from the `page`th listing page
+async fn get_image_pages<'a>(
+    gallery_url: &str,
+    page: usize,
+) -> Result<Vec<String>, ExtractionError<'a>> {
+    let sel = compile!(selector "div#gdt div.gdtm div a")?;
+    let url = format!("{}?p={}", gallery_url, page);
+    let (_, html) = get_gallery_html(url, &[]).await?;
+
+    let urls = html
+        .select(&sel)
+        .filter_map(|image| image.value().attr("href"))
+        .map(|url| url.to_string())
+        .collect::<Vec<String>>();
+    debug!("{} image(s) on listing page {}", urls.len(), page + 1);
+
+    Ok(urls)
 }
 
 async fn get_image_data<'a>(image: &mut gallery::Image) -> Result<(), ExtractionError<'a>> {
     let html = get_html(image.get_url()).await?;
     let filename = compile! { selector "div#i2 div" }?;
-    let image_url = compile! { selector "div#i3 a img" }?;
-    let url = html
-        .select(&image_url)
-        .nth(0)
-        .ok_or(ExtractionError::EmptyData("selector div #i3 a img"))?
-        .value()
-        .attr("src")
-        .unwrap()
-        .to_string();
+    let original = compile! { selector "div#i6 a[href*='fullimg']" }?;
 
-    image.set_url(url);
+    // The page url is about to be replaced by the image's, and
+    // it's the only place that tells us what the image hashes to.
+    // That hash is of the original file, which is only what the
+    // page shows when it doesn't offer to download the original
+    let hash =
+        crate::parser::get_image_hash(image.get_url()).map_err(ExtractionError::DataParseError)?;
+    image.set_hash(hash);
+    image.set_resampled(html.select(&original).next().is_some());
+
+    image.set_url(get_image_source(&html)?);
+    image.set_nl(get_nl_token(&html)?);
 
     let filename_raw = html
         .select(&filename)
@@ -176,13 +568,138 @@ async fn get_image_data<'a>(image: &mut gallery::Image) -> Result<(), Extraction
         .text()
         .collect::<String>();
 
-    let filename = crate::parser::get_filename(&filename_raw)
+    let info = crate::parser::get_image_info(&filename_raw)
         .map_err(|e| ExtractionError::DataParseError(e))?;
 
-    image.set_filename(filename);
+    image.set_filename(info.filename.clone());
+    image.set_original_name(info.filename);
+    image.set_dimensions(Some((info.width, info.height)));
+    image.set_declared_size(Some(info.size));
+
+    if let Some(link) = html.select(&original).next() {
+        if use_originals() {
+            get_original(image, link)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Points `image` to another image server, as if "Reload broken
+/// image" was clicked on its page. Returns whether there was
+/// a server to switch to
+pub async fn reload_image<'a>(image: &mut gallery::Image) -> Result<bool, ExtractionError<'a>> {
+    let Some(nl) = image.get_nl() else {
+        return Ok(false);
+    };
+
+    let html = get_html(format!("{}?nl={}", image.get_page(), nl)).await?;
+    image.set_url(get_image_source(&html)?);
+    image.set_nl(get_nl_token(&html)?);
+
+    Ok(true)
+}
+
+/// Gets `image`'s page again for the image it shows now, which is
+/// how one that was served as the image limit notice is picked back
+/// up once the limit resets
+pub async fn refresh_image<'a>(image: &mut gallery::Image) -> Result<(), ExtractionError<'a>> {
+    let html = get_html(image.get_page().clone()).await?;
+    image.set_url(get_image_source(&html)?);
+    image.set_nl(get_nl_token(&html)?);
+
+    Ok(())
+}
+
+fn get_image_source<'a>(html: &Html) -> Result<String, ExtractionError<'a>> {
+    let image_url = compile! { selector "div#i3 a img" }?;
+
+    Ok(html
+        .select(&image_url)
+        .nth(0)
+        .and_then(|image| image.value().attr("src"))
+        .ok_or(ExtractionError::EmptyData("selector div #i3 a img"))?
+        .to_string())
+}
+
+fn get_nl_token<'a>(html: &Html) -> Result<Option<String>, ExtractionError<'a>> {
+    let reload = compile! { selector "a#loadfail" }?;
+
+    match html
+        .select(&reload)
+        .next()
+        .and_then(|link| link.value().attr("onclick"))
+    {
+        Some(onclick) => {
+            crate::parser::get_nl_token(onclick).map_err(ExtractionError::DataParseError)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Points `image` to the original behind the page's "Download
+/// original" `link`, keeping what the page shows as a fallback.
+/// The link redirects to an image server, which the client follows
+fn get_original<'a>(
+    image: &mut gallery::Image,
+    link: ElementRef,
+) -> Result<(), ExtractionError<'a>> {
+    let url = link
+        .value()
+        .attr("href")
+        .ok_or(ExtractionError::EmptyData("original image link"))?
+        .to_string();
+    let info = crate::parser::get_original_info(&link.text().collect::<String>()).ok();
+
+    let mut fallback = image.clone();
+    fallback.set_fallback(None);
+
+    image.set_url(url);
+    // Reloading the page only gets the resampled image from
+    // another server, which is what the fallback is for
+    image.set_nl(None);
+    image.set_resampled(false);
+    image.set_dimensions(info.map(|(width, height, _)| (width, height)));
+    image.set_declared_size(info.map(|(_, _, size)| size));
+    image.set_fallback(Some(fallback));
+
     Ok(())
 }
 
+/// Originals are only worth asking for when there's
+/// an account to spend the quota they take on
+fn use_originals() -> bool {
+    get_originals_setting() && auth::get_credentials().is_logged_in()
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        fn get_originals_setting() -> bool {
+            crate::CONFIG.download.originals
+        }
+
+        fn get_follow_newer_setting() -> bool {
+            crate::CONFIG.download.follow_newer
+        }
+    } else if #[cfg(feature = "cli")] {
+        fn get_originals_setting() -> bool {
+            crate::ARGS.originals
+        }
+
+        fn get_follow_newer_setting() -> bool {
+            crate::ARGS.follow_newer
+        }
+    } else {
+        fn get_originals_setting() -> bool {
+            false
+        }
+
+        fn get_follow_newer_setting() -> bool {
+            false
+        }
+    }
+}
+
 fn get_tags<'a>(gallery: &mut gallery::Gallery, html: &Html) -> Result<(), ExtractionError<'a>> {
     let tag_types = compile! { selector "div#taglist table tbody tr" }?;
     let tag_name = compile! { selector "tr td" }?;
@@ -205,3 +722,108 @@ fn get_tags<'a>(gallery: &mut gallery::Gallery, html: &Html) -> Result<(), Extra
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(taken: &[&str]) -> ImagesState {
+        ImagesState {
+            page: 0,
+            queue: VecDeque::new(),
+            index: 0,
+            known: HashMap::new(),
+            taken: taken.iter().map(|name| name.to_lowercase()).collect(),
+        }
+    }
+
+    #[test]
+    fn duplicate_names_are_suffixed() {
+        let mut state = state(&[]);
+        assert_eq!(state.take("1.jpg"), "1.jpg");
+        assert_eq!(state.take("1.JPG"), "1 (2).JPG");
+        assert_eq!(state.take("1.jpg"), "1 (3).jpg");
+        assert_eq!(state.take("cover"), "cover");
+        assert_eq!(state.take("cover"), "cover (2)");
+    }
+
+    #[test]
+    fn downloaded_names_stay_taken() {
+        // As restored from the journal, including a suffixed duplicate
+        let mut state = state(&["1.jpg", "1 (2).jpg"]);
+        assert_eq!(state.take("1.jpg"), "1 (3).jpg");
+        assert_eq!(state.take("2.jpg"), "2.jpg");
+    }
+
+    const GALLERY_PAGE: &str = r#"<html><body>
+        <div id="gd2"><h1 id="gn">Mock Gallery</h1><h1 id="gj">モック</h1></div>
+        <div id="gdt"><div class="gdtm"><div><a href="https://e-hentai.org/s/0a1b2c3d4e/1234567-1">1</a></div></div></div>
+        </body></html>"#;
+
+    fn unavailable(page: &str) -> Option<Unavailable> {
+        get_unavailable(&Html::parse_fragment(page)).unwrap()
+    }
+
+    #[test]
+    fn notices_are_told_apart() {
+        assert_eq!(
+            unavailable("<html><p>This gallery has been removed or is unavailable.</p></html>"),
+            Some(Unavailable::Removed)
+        );
+        assert_eq!(
+            unavailable("<html><p>Gallery not found. If you just added this gallery, you may have to wait a short while before it becomes available.</p></html>"),
+            Some(Unavailable::Removed)
+        );
+        assert_eq!(
+            unavailable(
+                r#"<html><div class="d"><p>This gallery has been expunged.</p></div></html>"#
+            ),
+            Some(Unavailable::Expunged)
+        );
+        assert_eq!(
+            unavailable("Key missing, or incorrect key provided."),
+            Some(Unavailable::KeyMissing)
+        );
+        assert_eq!(unavailable("<html><p>Something else</p></html>"), None);
+    }
+
+    #[test]
+    fn galleries_are_not_notices() {
+        assert_eq!(unavailable(GALLERY_PAGE), None);
+
+        // Whatever the gallery happens to be called
+        let page = GALLERY_PAGE.replace("Mock Gallery", "Key missing: expunged and removed");
+        assert_eq!(unavailable(&page), None);
+    }
+
+    #[test]
+    fn latest_newer_version_is_picked() {
+        let page = GALLERY_PAGE.replace(
+            r#"<div id="gdt">"#,
+            r#"<div id="gnd">There are newer versions of this gallery available:<br>
+            <a href="https://e-hentai.org/g/1234500/aaaaaa0123/">v2</a>, added 2024-01-01<br>
+            <a href="https://e-hentai.org/g/1234599/bbbbbb0123/">v3</a>, added 2025-01-01</div>
+            <div id="gdt">"#,
+        );
+        assert_eq!(
+            get_newer_version(&Html::parse_fragment(&page)).unwrap(),
+            Some("https://e-hentai.org/g/1234599/bbbbbb0123/".to_string())
+        );
+        assert_eq!(
+            get_newer_version(&Html::parse_fragment(GALLERY_PAGE)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn content_warnings_are_recognized() {
+        let warning = Html::parse_fragment(
+            r#"<html><h1>Content Warning</h1><p>This gallery has been flagged as Offensive For Everyone.</p>
+            <a href="https://e-hentai.org/g/1234567/abcdef0123/?nw=session">View Gallery</a>
+            <a href="https://e-hentai.org/g/1234567/abcdef0123/?nw=always">Never Warn Me Again</a>
+            <a href="https://e-hentai.org/">Get Me Outta Here</a></html>"#,
+        );
+        assert!(is_content_warning(&warning).unwrap());
+        assert!(!is_content_warning(&Html::parse_fragment(GALLERY_PAGE)).unwrap());
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt::Display;
+
+use log::info;
+
+use crate::auth;
+use crate::extractor::ExtractionError;
+use crate::parser;
+use crate::progress::Progress;
+use crate::search;
+use crate::state::{self, GalleryStatus};
+
+const FAVORITES_URL: &str = "https://e-hentai.org/favorites.php";
+
+#[derive(Debug)]
+pub enum SyncError<'a> {
+    ExtractionError(ExtractionError<'a>),
+}
+
+impl<'a> Display for SyncError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ExtractionError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Lists the account's favorites (only the configured categories,
+/// or all of them) that the state journal hasn't seen packaged yet
+pub async fn get_new_favorites<'a>(progress: &Progress) -> Result<Vec<String>, SyncError<'a>> {
+    if !auth::get_credentials().is_logged_in() {
+        return Err(SyncError::ExtractionError(ExtractionError::LoginRequired));
+    }
+
+    let categories = get_categories();
+    let urls = if categories.is_empty() {
+        vec![FAVORITES_URL.to_string()]
+    } else {
+        categories
+            .iter()
+            .map(|cat| format!("{}?favcat={}", FAVORITES_URL, cat))
+            .collect()
+    };
+
+    let mut favorites = vec![];
+    for url in urls {
+        favorites.extend(
+            search::get_galleries(&url, None, progress)
+                .await
+                .map_err(SyncError::ExtractionError)?,
+        );
+    }
+
+    let total = favorites.len();
+    favorites.retain(|gallery| match parser::get_gallery_id(gallery) {
+        Ok((gid, _)) => state::get_state().gallery(gid) != Some(GalleryStatus::Packaged),
+        Err(_) => true,
+    });
+
+    info!(
+        "{} favorite(s), {} of which are new",
+        total,
+        favorites.len()
+    );
+    Ok(favorites)
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        pub fn is_enabled() -> bool {
+            crate::CONFIG.favorites.sync
+        }
+
+        fn get_categories() -> Vec<u8> {
+            crate::CONFIG.favorites.categories.clone()
+        }
+    } else if #[cfg(feature = "cli")] {
+        pub fn is_enabled() -> bool {
+            crate::ARGS.sync_favorites
+        }
+
+        fn get_categories() -> Vec<u8> {
+            crate::ARGS.favorite_categories.clone()
+        }
+    } else {
+        pub fn is_enabled() -> bool {
+            false
+        }
+
+        fn get_categories() -> Vec<u8> {
+            vec![]
+        }
+    }
+}
@@ -6,16 +6,50 @@ type Images<'a> = Iter<'a, Image>;
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub struct Gallery {
+    gid: u64,
+    token: String,
+    url: String,
+    listing_pages: usize,
     title: String,
-    image_count: u8,
+    title_jpn: String,
+    category: String,
+    uploader: String,
+    posted: Option<i64>,
+    file_count: Option<u32>,
+    file_size: Option<u64>,
+    /// Stored in hundredths so that `Gallery` can stay `Eq + Hash`
+    rating: Option<u16>,
+    /// Where the latest version of the gallery is, if it has one
+    newer_version: Option<String>,
+    image_count: usize,
     images: Vec<Image>,
     tags: Tags,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Image {
     url: String,
     file: String,
+    /// The name the site gives the image, which `file`
+    /// may differ from depending on the naming mode
+    original: String,
+    /// Width and height, as the image page states them
+    dimensions: Option<(u32, u32)>,
+    /// Size in bytes, as the image page states it
+    declared_size: Option<u64>,
+    /// The first 10 hex digits of the original image's SHA-1
+    hash: Option<String>,
+    /// Whether `url` points to a resampled version of the image,
+    /// which `hash` doesn't apply to
+    resampled: bool,
+    /// What to download instead if `url` can't be,
+    /// i.e. the resampled version of an original
+    fallback: Option<Box<Image>>,
+    /// The image page `url` was found on
+    page: String,
+    /// The page's "Reload broken image" token, which
+    /// gets `url` from another image server
+    nl: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -36,27 +70,99 @@ pub enum TagType {
     Character,
     Language,
     Artist,
+    Group,
+    Cosplayer,
     Male,
     Female,
+    Mixed,
     Other,
 }
 
 impl Gallery {
     pub fn new() -> Self {
         Self {
+            gid: 0,
+            token: String::new(),
+            url: String::new(),
+            listing_pages: 0,
             title: String::new(),
+            title_jpn: String::new(),
+            category: String::new(),
+            uploader: String::new(),
+            posted: None,
+            file_count: None,
+            file_size: None,
+            rating: None,
+            newer_version: None,
             image_count: 0,
             images: vec![],
             tags: Tags::new(),
         }
     }
 
+    pub fn set_id(&mut self, gid: u64, token: String) {
+        self.gid = gid;
+        self.token = token;
+    }
+
+    pub fn set_url(&mut self, url: String) {
+        self.url = url;
+    }
+
+    /// How many listing pages (`?p=`) it takes to show every image
+    pub fn set_listing_pages(&mut self, pages: usize) {
+        self.listing_pages = pages;
+    }
+
     pub fn set_title(&mut self, title: String) {
         self.title = crate::sanitize::sanitize(&title);
     }
 
+    pub fn set_title_jpn(&mut self, title: String) {
+        self.title_jpn = title;
+    }
+
+    pub fn set_category(&mut self, category: String) {
+        self.category = category;
+    }
+
+    pub fn set_uploader(&mut self, uploader: String) {
+        self.uploader = uploader;
+    }
+
+    /// `posted` is a unix timestamp
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn set_posted(&mut self, posted: Option<i64>) {
+        self.posted = posted;
+    }
+
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn set_file_count(&mut self, count: Option<u32>) {
+        self.file_count = count;
+    }
+
+    /// `size` is in bytes
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn set_file_size(&mut self, size: Option<u64>) {
+        self.file_size = size;
+    }
+
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn set_rating(&mut self, rating: Option<f32>) {
+        self.rating = rating.map(|r| (r * 100.0).round() as u16);
+    }
+
+    pub fn set_newer_version(&mut self, url: Option<String>) {
+        self.newer_version = url;
+    }
+
+    /// How many images the gallery says it has, which is known
+    /// well before all of them are extracted
+    pub fn set_len(&mut self, count: usize) {
+        self.image_count = count;
+    }
+
     pub fn add_image(&mut self, image: Image) {
-        self.image_count += 1;
         self.images.push(image);
     }
 
@@ -69,12 +175,64 @@ impl Gallery {
         self.tags.push(tag);
     }
 
+    pub fn gid(&self) -> u64 {
+        self.gid
+    }
+
+    pub fn token(&self) -> &String {
+        &self.token
+    }
+
+    pub fn url(&self) -> &String {
+        &self.url
+    }
+
+    pub fn listing_pages(&self) -> usize {
+        self.listing_pages
+    }
+
     pub fn title(&self) -> &String {
         &self.title
     }
 
-    pub const fn len(&self) -> u8 {
-        self.image_count
+    pub fn title_jpn(&self) -> &String {
+        &self.title_jpn
+    }
+
+    pub fn category(&self) -> &String {
+        &self.category
+    }
+
+    pub fn uploader(&self) -> &String {
+        &self.uploader
+    }
+
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn posted(&self) -> Option<i64> {
+        self.posted
+    }
+
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn file_count(&self) -> Option<u32> {
+        self.file_count
+    }
+
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn file_size(&self) -> Option<u64> {
+        self.file_size
+    }
+
+    #[cfg_attr(not(feature = "api"), allow(dead_code))]
+    pub fn rating(&self) -> Option<f32> {
+        self.rating.map(|r| r as f32 / 100.0)
+    }
+
+    pub fn newer_version(&self) -> Option<&String> {
+        self.newer_version.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.image_count.max(self.images.len())
     }
 
     pub fn images(&self) -> Images<'_> {
@@ -91,6 +249,14 @@ impl Image {
         Self {
             url: url.clone(),
             file: String::new(),
+            original: String::new(),
+            dimensions: None,
+            declared_size: None,
+            hash: None,
+            resampled: false,
+            fallback: None,
+            page: url.clone(),
+            nl: None,
         }
     }
 
@@ -102,6 +268,68 @@ impl Image {
         &self.file
     }
 
+    pub fn set_original_name(&mut self, original: String) {
+        self.original = original;
+    }
+
+    pub fn get_original_name(&self) -> &String {
+        &self.original
+    }
+
+    pub fn set_dimensions(&mut self, dimensions: Option<(u32, u32)>) {
+        self.dimensions = dimensions;
+    }
+
+    pub fn get_dimensions(&self) -> Option<(u32, u32)> {
+        self.dimensions
+    }
+
+    pub fn set_declared_size(&mut self, size: Option<u64>) {
+        self.declared_size = size;
+    }
+
+    pub fn get_declared_size(&self) -> Option<u64> {
+        self.declared_size
+    }
+
+    pub fn set_hash(&mut self, hash: Option<String>) {
+        self.hash = hash;
+    }
+
+    /// The hash the downloaded file should start with, if it's
+    /// known and applies to what `url` points to
+    pub fn get_expected_hash(&self) -> Option<&String> {
+        if self.resampled {
+            None
+        } else {
+            self.hash.as_ref()
+        }
+    }
+
+    pub fn set_resampled(&mut self, resampled: bool) {
+        self.resampled = resampled;
+    }
+
+    pub fn set_fallback(&mut self, fallback: Option<Image>) {
+        self.fallback = fallback.map(Box::new);
+    }
+
+    pub fn take_fallback(&mut self) -> Option<Image> {
+        self.fallback.take().map(|fallback| *fallback)
+    }
+
+    pub fn get_page(&self) -> &String {
+        &self.page
+    }
+
+    pub fn set_nl(&mut self, nl: Option<String>) {
+        self.nl = nl;
+    }
+
+    pub fn get_nl(&self) -> Option<&String> {
+        self.nl.as_ref()
+    }
+
     pub fn set_url(&mut self, url: String) {
         self.url = url;
     }
@@ -126,8 +354,11 @@ impl From<String> for TagType {
             "character" => Self::Character,
             "language" => Self::Language,
             "artist" => Self::Artist,
+            "group" => Self::Group,
+            "cosplayer" => Self::Cosplayer,
             "male" => Self::Male,
             "female" => Self::Female,
+            "mixed" => Self::Mixed,
             "other" | _ => Self::Other,
         }
     }
@@ -141,8 +372,11 @@ impl ToString for TagType {
             Self::Character => String::from("character"),
             Self::Language => String::from("language"),
             Self::Artist => String::from("artist"),
+            Self::Group => String::from("group"),
+            Self::Cosplayer => String::from("cosplayer"),
             Self::Male => String::from("male"),
             Self::Female => String::from("female"),
+            Self::Mixed => String::from("mixed"),
             Self::Other => String::from("other"),
         }
     }
@@ -0,0 +1,227 @@
+use std::fmt::{Debug, Display};
+use std::fs::read;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info};
+use reqwest::cookie::Jar;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, IntoUrl, Proxy, RequestBuilder, Response};
+use tokio::time::{error::Elapsed, timeout};
+
+use crate::auth;
+use crate::ratelimit::{self, Budget};
+
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+/// In seconds
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 15;
+/// In seconds. For images this is how long we wait for the next
+/// chunk, not for the whole file
+pub const DEFAULT_READ_TIMEOUT: u64 = 30;
+
+#[derive(Debug)]
+pub enum HttpError {
+    ProxyError(reqwest::Error),
+    CertReadError(PathBuf, std::io::Error),
+    CertParseError(reqwest::Error),
+    InvalidHeader(String),
+    BuildError(reqwest::Error),
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "error while {}",
+            match self {
+                Self::ProxyError(e) => format!("setting up proxy: {}", e),
+                Self::CertReadError(p, e) => format!("reading CA bundle {:?}: {}", p, e),
+                Self::CertParseError(e) => format!("parsing CA bundle: {}", e),
+                Self::InvalidHeader(h) => format!("parsing header {:?}: expected `Name: value`", h),
+                Self::BuildError(e) => format!("building http client: {}", e),
+            }
+        )
+    }
+}
+
+/// Everything the shared client is built from
+#[derive(Clone)]
+pub struct ClientOptions {
+    pub user_agent: String,
+    pub proxy: Option<String>,
+    pub connect_timeout: u64,
+    pub read_timeout: u64,
+    pub headers: Vec<String>,
+    pub ca_bundle: Option<PathBuf>,
+}
+
+// Manually implemented so that headers, which may well be
+// carrying cookies, never end up in the logs
+impl Debug for ClientOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientOptions")
+            .field("user_agent", &self.user_agent)
+            .field("proxy", &self.proxy)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("read_timeout", &self.read_timeout)
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .iter()
+                    .map(|header| match header.split_once(':') {
+                        Some((name, _)) => format!("{}: <redacted>", name.trim()),
+                        None => "<redacted>".to_string(),
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .field("ca_bundle", &self.ca_bundle)
+            .finish()
+    }
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            proxy: None,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            headers: vec![],
+            ca_bundle: None,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref OPTIONS: ClientOptions = get_options();
+    static ref CLIENT: Client = build_client(&OPTIONS)
+        .map_err(|e| {
+            eprintln!("{}", e);
+            exit(-1);
+        })
+        .unwrap();
+}
+
+/// Builds the shared client right away, so that a bad proxy
+/// or CA bundle is reported before any download starts
+pub fn init() {
+    lazy_static::initialize(&CLIENT);
+}
+
+pub fn client() -> &'static Client {
+    &CLIENT
+}
+
+pub fn read_timeout() -> Duration {
+    Duration::from_secs(OPTIONS.read_timeout)
+}
+
+pub async fn get<U: IntoUrl>(url: U, budget: Budget) -> reqwest::Result<Response> {
+    send(CLIENT.get(url), budget).await
+}
+
+/// Every request should be sent through here, so that it's
+/// paced by the rate limiter of its `budget`
+pub async fn send(request: RequestBuilder, budget: Budget) -> reqwest::Result<Response> {
+    ratelimit::acquire(budget).await;
+
+    match budget {
+        // Pages are small enough that waiting for all of it
+        // is the same as waiting for the next read
+        Budget::Html => request.timeout(read_timeout()).send().await,
+        Budget::Image => request.send().await,
+    }
+}
+
+/// Sends an image request through the image rate limiter. Images can
+/// take a lot longer than the read timeout to arrive in full, so only
+/// the headers are held to it here, and the body is left for the
+/// caller to watch. `Err` when the headers didn't arrive in time
+pub async fn send_image(request: RequestBuilder) -> Result<reqwest::Result<Response>, Elapsed> {
+    ratelimit::acquire(Budget::Image).await;
+
+    timeout(read_timeout(), request.send()).await
+}
+
+fn build_client(options: &ClientOptions) -> Result<Client, HttpError> {
+    debug!("Building http client with {:?}", options);
+
+    let jar = Jar::default();
+    auth::get_credentials().apply(&jar);
+
+    let mut builder = Client::builder()
+        .cookie_provider(Arc::new(jar))
+        .user_agent(&options.user_agent)
+        .connect_timeout(Duration::from_secs(options.connect_timeout))
+        .default_headers(parse_headers(&options.headers)?);
+
+    if let Some(proxy) = &options.proxy {
+        info!("Using proxy {}", proxy);
+        builder = builder.proxy(Proxy::all(proxy).map_err(HttpError::ProxyError)?);
+    }
+
+    if let Some(bundle) = &options.ca_bundle {
+        info!("Using CA bundle {:?}", bundle);
+        let pem = read(bundle).map_err(|e| HttpError::CertReadError(bundle.clone(), e))?;
+
+        for cert in Certificate::from_pem_bundle(&pem).map_err(HttpError::CertParseError)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    builder.build().map_err(HttpError::BuildError)
+}
+
+fn parse_headers(raw: &[String]) -> Result<HeaderMap, HttpError> {
+    let mut headers = HeaderMap::new();
+
+    for header in raw {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| HttpError::InvalidHeader(header.clone()))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| HttpError::InvalidHeader(header.clone()))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| HttpError::InvalidHeader(header.clone()))?;
+
+        headers.insert(name, value);
+    }
+
+    Ok(headers)
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "config", not(test)))] {
+        fn get_options() -> ClientOptions {
+            let network = &crate::CONFIG.network;
+
+            ClientOptions {
+                user_agent: network.user_agent.clone(),
+                proxy: network.proxy.clone(),
+                connect_timeout: network.connect_timeout,
+                read_timeout: network.read_timeout,
+                headers: network.headers.clone(),
+                ca_bundle: network.ca_bundle.clone(),
+            }
+        }
+    } else if #[cfg(all(feature = "cli", not(test)))] {
+        fn get_options() -> ClientOptions {
+            ClientOptions {
+                user_agent: crate::ARGS.user_agent.clone(),
+                proxy: crate::ARGS.proxy.clone(),
+                connect_timeout: crate::ARGS.connect_timeout,
+                read_timeout: crate::ARGS.read_timeout,
+                headers: crate::ARGS.headers.clone(),
+                ca_bundle: crate::ARGS.ca_bundle.clone(),
+            }
+        }
+    } else {
+        fn get_options() -> ClientOptions {
+            ClientOptions::default()
+        }
+    }
+}
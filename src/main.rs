@@ -1,9 +1,12 @@
+use std::collections::{BTreeMap, HashSet};
 use std::path::PathBuf;
 use std::process::exit;
 
 #[cfg(any(feature = "config", feature = "cli"))]
 use std::sync::Arc;
 
+#[cfg(feature = "api")]
+use log::warn;
 use log::{debug, error, info};
 
 cfg_if::cfg_if! {
@@ -24,10 +27,15 @@ mod macros;
 
 #[cfg_attr(not(feature = "aniyomi"), allow(unused_imports))]
 mod aniyomi;
+#[cfg(feature = "api")]
+mod api;
+mod atomic;
+mod auth;
 #[cfg_attr(not(feature = "cli"), allow(unused_imports))]
 mod cli;
 #[cfg_attr(not(feature = "config"), allow(unused_imports))]
 mod config;
+mod conflict;
 #[cfg_attr(not(feature = "zip"), allow(dead_code))]
 mod downloader;
 #[cfg_attr(not(feature = "aniyomi"), allow(dead_code))]
@@ -36,11 +44,21 @@ mod gallery;
 mod zip;
 
 mod extractor;
+mod favorites;
+mod http;
 #[cfg_attr(not(feature = "config"), allow(dead_code))]
 mod logger;
+mod naming;
 mod parser;
 mod progress;
+mod quota;
+mod ratelimit;
+mod retry;
 mod sanitize;
+mod scheduler;
+mod search;
+mod state;
+mod template;
 mod version;
 
 const CHUNK_SIZE: usize = 1024;
@@ -67,7 +85,8 @@ cfg_if::cfg_if! {
 #[tokio::main]
 async fn main() {
     let version = version::get_version();
-    let mut errs = 0;
+    // Errors by what kind of problem they are
+    let mut errs: BTreeMap<&'static str, i32> = BTreeMap::new();
 
     cfg_if::cfg_if! {
         if #[cfg(feature = "cli")] {
@@ -84,69 +103,112 @@ async fn main() {
     info!("{}", version);
     info!("Using log level {:?}", log_level);
 
+    http::init();
+
+    if let Err(err) = template::validate(&template::get_template()) {
+        eprintln!("{}", err);
+        exit(-1);
+    }
+
+    // Galleries are at most as deep as the template goes, and their
+    // files one level further, or two in the aniyomi layout
+    let template = template::get_template();
+    atomic::clean(&template::get_output(), template.split('/').count() + 2);
+
     let m_prog = progress::Progress::new();
 
-    let file = get_file();
-    let raw = parser::read_file::<CHUNK_SIZE, PathBuf>(&file).unwrap();
-    let galleries = parser::get_all_galleries(&raw).unwrap();
-    let gallery_prog = m_prog.add_prog(galleries.len() as u64, "Getting Galleries");
+    let mut galleries = vec![];
+    if let Some(file) = get_file() {
+        let raw = parser::read_file::<CHUNK_SIZE, PathBuf>(&file).unwrap();
+        galleries.extend(get_galleries(&raw, &m_prog, &mut errs).await);
+    }
+
+    if favorites::is_enabled() {
+        match favorites::get_new_favorites(&m_prog).await {
+            Ok(found) => galleries.extend(found),
+            Err(err) => {
+                error!(
+                    "Error while syncing favorites: {0}\nFull Error:\n{0:#?}",
+                    err
+                );
+                *errs.entry("favorites").or_insert(0) += 1;
+            }
+        }
+    }
+
+    // The same gallery may be linked directly and also show up in
+    // a search, it should still only be downloaded once
+    let mut seen = HashSet::new();
+    galleries.retain(|gallery| match parser::get_gallery_id(gallery) {
+        Ok((gid, _)) => seen.insert(gid),
+        Err(_) => true,
+    });
+
+    // Whatever a previous run already packaged is left alone when
+    // skipping, otherwise it's checked for missing images like
+    // everything else, which picks up where it was left
+    let state = state::get_state();
+    let skip = conflict::get_policy() == conflict::ConflictPolicy::Skip;
+    galleries.retain(|gallery| match parser::get_gallery_id(gallery) {
+        Ok((gid, _)) if skip && state.gallery(gid) == Some(state::GalleryStatus::Packaged) => {
+            info!("Skipping {:?}, it was downloaded already", gallery);
+            false
+        }
+        Ok((gid, _)) => {
+            if state.gallery(gid).is_none() {
+                state.set_gallery(gid, state::GalleryStatus::Pending);
+            }
+            true
+        }
+        Err(_) => true,
+    });
 
     #[cfg(feature = "metrics")]
     let mut download_totals = HashMap::new();
 
     info!("{} galleries to download", galleries.len());
-    for gallery in galleries {
-        info!("fetching data for {:?}", gallery);
-        let gallery = extractor::get_gallery(&gallery, &m_prog).await;
-
-        if let Err(ref err) = gallery {
-            error!(
-                "Error while extracting data for gallery: {0}\nFull Error:\n{0:#?}",
-                err
-            );
-            errs += 1;
 
-            continue;
+    #[cfg(feature = "api")]
+    if api::is_enabled() {
+        let ids = galleries
+            .iter()
+            .filter_map(|gallery| parser::get_gallery_id(gallery).ok())
+            .collect::<Vec<_>>();
+
+        if let Err(err) = api::prefetch(&ids).await {
+            warn!("Unable to prefetch gallery metadata: {}", err);
         }
+    }
 
-        let gallery = gallery.unwrap();
-        info!("downloading gallery {:?}", gallery.title());
+    // Galleries that were downloaded even though they have newer versions
+    let mut outdated = vec![];
 
-        #[cfg(feature = "metrics")]
-        match downloader::download_gallery::<CHUNK_SIZE>(&gallery, &m_prog).await {
-            Ok(downloads) => {
+    for (url, result) in scheduler::run::<CHUNK_SIZE>(&galleries, &m_prog).await {
+        match result {
+            #[allow(unused_variables)]
+            Ok((gallery, downloads)) => {
+                if let Some(newer) = gallery.newer_version() {
+                    outdated.push((url, newer.clone()));
+                }
+
+                #[cfg(feature = "metrics")]
                 download_totals.insert(gallery, downloads);
             }
             Err(err) => {
-                error!(
-                    "Error while downloading gallery {1:?}: {0}\nFull Error:\n{0:#?}",
-                    err,
-                    gallery.title()
-                );
-                errs += 1;
+                *errs.entry(err.category()).or_insert(0) += 1;
+
+                match err {
+                    scheduler::GalleryError::ExtractionError(err) => error!(
+                        "Error while extracting data for gallery {1:?}: {0}\nFull Error:\n{0:#?}",
+                        err, url
+                    ),
+                    scheduler::GalleryError::DownloadError(err) => error!(
+                        "Error while downloading gallery {1:?}: {0}\nFull Error:\n{0:#?}",
+                        err, url
+                    ),
+                }
             }
         }
-
-        #[cfg(not(feature = "metrics"))]
-        if let Err(err) = downloader::download_gallery::<CHUNK_SIZE>(&gallery, &m_prog) {
-            error!(
-                "Error while downloading gallery {1:?}: {0}\nFull Error:\n{0:#?}",
-                err,
-                gallery.title()
-            );
-            errs += 1;
-        }
-
-        gallery_prog.inc(1);
-    }
-    gallery_prog.finish_and_clear();
-
-    if errs < 0 {
-        eprintln!(
-            "{} error(s) have occurred while downloading, check the logs for more info",
-            errs
-        );
-        exit(errs);
     }
 
     cfg_if::cfg_if! {
@@ -164,21 +226,89 @@ async fn main() {
             println!("Downloaded the following Galleries:\n\n{}", table);
         }
     }
+
+    if !outdated.is_empty() {
+        println!(
+            "\n{} gallery(s) have a newer version, which --follow-newer downloads instead:",
+            outdated.len()
+        );
+        for (url, newer) in &outdated {
+            println!("  {} -> {}", url, newer);
+        }
+    }
+
+    let total = errs.values().sum::<i32>();
+    if total > 0 {
+        eprintln!(
+            "{} error(s) have occurred while downloading, check the logs for more info:",
+            total
+        );
+        for (category, count) in &errs {
+            eprintln!("  {}: {}", category, count);
+        }
+        // Only the low byte of the status makes it out, a count
+        // of failures could come out as success
+        exit(1);
+    }
+}
+
+/// Collects the galleries linked in `raw`, expanding any search,
+/// tag or uploader pages into the galleries they list. Pages that
+/// can't be listed are counted in `errs`
+async fn get_galleries(
+    raw: &str,
+    m_prog: &progress::Progress,
+    errs: &mut BTreeMap<&'static str, i32>,
+) -> Vec<String> {
+    let mut galleries = parser::get_all_galleries(raw).unwrap();
+
+    for listing in parser::get_all_listings(raw).unwrap() {
+        match search::get_galleries(&listing, get_max_results(), m_prog).await {
+            Ok(found) => galleries.extend(found),
+            Err(err) => {
+                error!(
+                    "Error while enumerating galleries from {:?}: {}\nFull Error:\n{:#?}",
+                    listing, err, err
+                );
+                *errs.entry("search").or_insert(0) += 1;
+            }
+        }
+    }
+
+    galleries
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        fn get_max_results() -> Option<usize> {
+            CONFIG.search.max_results
+        }
+    } else if #[cfg(feature = "cli")] {
+        fn get_max_results() -> Option<usize> {
+            ARGS.max_results
+        }
+    } else {
+        fn get_max_results() -> Option<usize> {
+            None
+        }
+    }
 }
 
 #[cfg(feature = "cli")]
-fn get_file() -> PathBuf {
-    let file = ARGS.links_file.clone();
+fn get_file() -> Option<PathBuf> {
+    let file = ARGS.links_file.clone()?;
 
     debug!("File Path is {}", &file.as_path().display());
 
-    file
+    Some(file)
 }
 
 #[cfg(not(feature = "cli"))]
-fn get_file() -> PathBuf {
+fn get_file() -> Option<PathBuf> {
     let raw_path = match args().nth(1) {
         Some(p) => p,
+        // Syncing favorites doesn't need an input file
+        None if favorites::is_enabled() => return None,
         None => {
             eprintln!("No file to read from was provided");
             exit(0x404)
@@ -189,5 +319,5 @@ fn get_file() -> PathBuf {
 
     debug!("File Path is {}", &file.as_path().display());
 
-    file
+    Some(file)
 }
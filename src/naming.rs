@@ -0,0 +1,144 @@
+use std::fmt::Display;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+
+use log::info;
+#[cfg(feature = "config")]
+use serde::{Deserialize, Serialize};
+
+use crate::atomic;
+use crate::gallery::Gallery;
+
+/// Written next to the images, mapping every page
+/// to the name the site had for it
+pub const MANIFEST_FILE: &str = "manifest.tsv";
+
+/// How images are named on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum NamingMode {
+    /// The name the site gives the image
+    #[default]
+    Original,
+    /// The page number, e.g. `007.jpg`
+    Index,
+    /// The page number followed by the name the site gives it,
+    /// e.g. `007 - scan_12.jpg`
+    IndexOriginal,
+}
+
+impl Display for NamingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Original => "original",
+                Self::Index => "index",
+                Self::IndexOriginal => "index-original",
+            }
+        )
+    }
+}
+
+impl NamingMode {
+    /// The name the `index`th image (counting from 0) of a gallery
+    /// with `total` images is saved under. Page numbers are padded
+    /// so that every page of the gallery sorts in order
+    pub fn name(&self, index: usize, total: usize, original: &str) -> String {
+        let width = total.max(1).to_string().len();
+        let page = format!("{:0width$}", index + 1, width = width);
+
+        match self {
+            Self::Original => original.to_string(),
+            Self::Index => match Path::new(original).extension() {
+                Some(ext) => format!("{}.{}", page, ext.to_string_lossy()),
+                None => page,
+            },
+            Self::IndexOriginal => format!("{} - {}", page, original),
+        }
+    }
+}
+
+/// Writes the manifest of `gallery` into `dir`, one line per page
+/// with its number, the file it was saved as, its original name,
+/// and the dimensions and size the image page gave for it
+pub fn write_manifest(gallery: &Gallery, dir: &Path) -> Result<PathBuf, io::Error> {
+    let path = dir.join(MANIFEST_FILE);
+    let mut manifest = atomic::create(&path)?;
+
+    writeln!(manifest, "page\tfile\toriginal\twidth\theight\tsize")?;
+    for (index, image) in gallery.images().enumerate() {
+        let (width, height) = match image.get_dimensions() {
+            Some((width, height)) => (width.to_string(), height.to_string()),
+            None => (String::new(), String::new()),
+        };
+        let size = image
+            .get_declared_size()
+            .map(|size| size.to_string())
+            .unwrap_or_default();
+
+        writeln!(
+            manifest,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            index + 1,
+            image.get_filename(),
+            image.get_original_name(),
+            width,
+            height,
+            size
+        )?;
+    }
+
+    atomic::persist(manifest, &path)?;
+
+    info!("Written manifest to {:?}", path);
+    Ok(path)
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        pub fn get_mode() -> NamingMode {
+            crate::CONFIG.download.naming
+        }
+    } else if #[cfg(feature = "cli")] {
+        pub fn get_mode() -> NamingMode {
+            crate::ARGS.naming
+        }
+    } else {
+        pub fn get_mode() -> NamingMode {
+            NamingMode::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_follow_the_mode() {
+        assert_eq!(
+            NamingMode::Original.name(6, 120, "scan_12.jpg"),
+            "scan_12.jpg"
+        );
+        assert_eq!(NamingMode::Index.name(6, 120, "scan_12.jpg"), "007.jpg");
+        assert_eq!(
+            NamingMode::IndexOriginal.name(6, 120, "scan_12.jpg"),
+            "007 - scan_12.jpg"
+        );
+    }
+
+    #[test]
+    fn pages_are_padded_to_the_gallery_size() {
+        assert_eq!(NamingMode::Index.name(0, 9, "a.png"), "1.png");
+        assert_eq!(NamingMode::Index.name(0, 10, "a.png"), "01.png");
+        assert_eq!(NamingMode::Index.name(0, 0, "a.png"), "1.png");
+        assert_eq!(NamingMode::Index.name(0, 10, "no extension"), "01");
+    }
+}
@@ -7,7 +7,30 @@ use std::path::Path;
 use std::sync::mpsc::{self, SyncSender};
 use std::{num, string, thread};
 
-type Pagination = f32;
+/// What a gallery's "Showing 1 - 40 of 1,234 images" line says
+#[derive(Debug, Clone, Copy)]
+pub struct Pagination {
+    pub per_page: usize,
+    pub total: usize,
+}
+
+impl Pagination {
+    /// How many listing pages it takes to show every image
+    pub fn pages(&self) -> usize {
+        self.total.div_ceil(self.per_page.max(1))
+    }
+}
+
+/// What an image page says about its image,
+/// e.g. "scan_12.jpg :: 1280 x 1810 :: 300.5 KiB"
+#[derive(Debug, Clone)]
+pub struct ImageInfo {
+    pub filename: String,
+    pub width: u32,
+    pub height: u32,
+    /// In bytes, rounded the way the page rounds it
+    pub size: u64,
+}
 
 #[derive(Debug)]
 /// Wraps various errors into one. `C` is generally used for
@@ -21,7 +44,8 @@ pub enum ParseError<const C: usize> {
     RegexParseError(regex::Error),
     StringEncodeError(string::FromUtf8Error),
     NoCapture,
-    IntParseError(num::ParseFloatError),
+    IntParseError(num::ParseIntError),
+    FloatParseError(num::ParseFloatError),
 }
 
 impl<const C: usize> Display for ParseError<C> {
@@ -36,6 +60,7 @@ impl<const C: usize> Display for ParseError<C> {
                 Self::StringEncodeError(e) => format!("error while decoding string: {}", e),
                 Self::NoCapture => format!("expected to parse something, got nothing"),
                 Self::IntParseError(e) => format!("error while parsing int: {}", e),
+                Self::FloatParseError(e) => format!("error while parsing float: {}", e),
             }
         )
     }
@@ -102,8 +127,8 @@ where
     Ok(contents)
 }
 
-pub fn get_all_galleries(raw: &String) -> Result<Vec<String>, ParseError<0>> {
-    let parser = compile! {regex r"https://e-hentai\.org/g/(\d{7})/([a-z0-9]{10})"}?;
+pub fn get_all_galleries(raw: &str) -> Result<Vec<String>, ParseError<0>> {
+    let parser = compile! {regex r"https://e[-x]hentai\.org/g/(\d+)/([a-z0-9]{10})"}?;
 
     Ok(parser
         .captures_iter(raw)
@@ -113,22 +138,197 @@ pub fn get_all_galleries(raw: &String) -> Result<Vec<String>, ParseError<0>> {
         .collect::<Vec<String>>())
 }
 
-pub fn get_pagination(raw: &String) -> Result<Pagination, ParseError<0>> {
-    let parser = compile! {regex r"Showing 1 - (\d+) of (\d+)"}?;
-    let caps = parser.captures(raw.as_str()).ok_or(ParseError::NoCapture)?;
+/// Splits a gallery url into its gid and token
+pub fn get_gallery_id(url: &str) -> Result<(u64, String), ParseError<0>> {
+    let parser = compile! {regex r"/g/(\d+)/([a-z0-9]{10})"}?;
+    let caps = parser.captures(url).ok_or(ParseError::NoCapture)?;
+
+    let gid = caps[1].parse::<u64>().map_err(ParseError::IntParseError)?;
 
-    let total = caps[2]
-        .parse::<Pagination>()
-        .map_err(|e| ParseError::IntParseError(e))?;
-    let rendered = caps[1]
-        .parse::<Pagination>()
-        .map_err(|e| ParseError::IntParseError(e))?;
+    Ok((gid, caps[2].to_string()))
+}
+
+/// Search results, tag pages and uploader pages, anything
+/// that lists galleries rather than being one
+pub fn get_all_listings(raw: &str) -> Result<Vec<String>, ParseError<0>> {
+    let parser = compile! {regex r"https://e[-x]hentai\.org/(?:\?\S*f_\w+=|tag/|uploader/)\S*"}?;
 
-    Ok(total / rendered)
+    Ok(parser
+        .find_iter(raw)
+        .map(|e| e.as_str().to_string())
+        .collect::<Vec<String>>())
 }
 
-pub fn get_filename(raw: &String) -> Result<String, ParseError<0>> {
-    let parser = compile! {regex r"([\w\d.]*) :: ([\dx ]*) :: ([\d.]* \w*)"}?;
+pub fn get_pagination(raw: &str) -> Result<Pagination, ParseError<0>> {
+    let parser = compile! {regex r"Showing ([\d,]+) - ([\d,]+) of ([\d,]+)"}?;
+    let caps = parser.captures(raw).ok_or(ParseError::NoCapture)?;
 
-    Ok(parser.captures(raw).ok_or(ParseError::NoCapture)?[1].to_string())
+    // Large galleries have their counts written as "1,234"
+    let parse = |raw: &str| raw.replace(',', "").parse::<usize>();
+
+    let first = parse(&caps[1]).map_err(ParseError::IntParseError)?;
+    let last = parse(&caps[2]).map_err(ParseError::IntParseError)?;
+    let total = parse(&caps[3]).map_err(ParseError::IntParseError)?;
+
+    Ok(Pagination {
+        per_page: (last + 1).saturating_sub(first),
+        total,
+    })
+}
+
+/// The start of the image's SHA-1, which image page urls
+/// (`/s/<hash>/<gid>-<page>`) are named after
+pub fn get_image_hash(url: &str) -> Result<Option<String>, ParseError<0>> {
+    let parser = compile! {regex r"/s/([0-9a-f]{10})/\d+-\d+"}?;
+
+    Ok(parser.captures(url).map(|captures| captures[1].to_string()))
+}
+
+/// The token in the "Reload broken image" link's
+/// `onclick="return nl('1234-567890')"`
+pub fn get_nl_token(onclick: &str) -> Result<Option<String>, ParseError<0>> {
+    let parser = compile! {regex r"nl\('([^']+)'\)"}?;
+
+    Ok(parser
+        .captures(onclick)
+        .map(|captures| captures[1].to_string()))
+}
+
+pub fn get_image_info(raw: &str) -> Result<ImageInfo, ParseError<0>> {
+    let parser = compile! {regex r"^\s*(.+?) :: (\d+) x (\d+) :: ([\d.]+) ([KMG]i?B|B)\s*$"}?;
+    let captures = parser.captures(raw).ok_or(ParseError::NoCapture)?;
+
+    Ok(ImageInfo {
+        filename: captures[1].to_string(),
+        width: captures[2].parse().map_err(ParseError::IntParseError)?,
+        height: captures[3].parse().map_err(ParseError::IntParseError)?,
+        size: get_size(&captures[4], &captures[5])?,
+    })
+}
+
+/// What the "Download original 2400 x 3400 4.5 MiB source" link
+/// says about the original image, as its width, height and size
+pub fn get_original_info(raw: &str) -> Result<(u32, u32, u64), ParseError<0>> {
+    let parser = compile! {regex r"(\d+) x (\d+) ([\d.]+) ([KMG]i?B|B)"}?;
+    let captures = parser.captures(raw).ok_or(ParseError::NoCapture)?;
+
+    Ok((
+        captures[1].parse().map_err(ParseError::IntParseError)?,
+        captures[2].parse().map_err(ParseError::IntParseError)?,
+        get_size(&captures[3], &captures[4])?,
+    ))
+}
+
+/// How many images the account has used up out of its limit, from
+/// `home.php`'s "You are currently at 1,234 towards a limit of 5,000"
+pub fn get_image_limit(raw: &str) -> Result<(u64, u64), ParseError<0>> {
+    let parser = compile! {regex r"(?s)currently at\s*([\d,]+)\s*towards.*?limit of\s*([\d,]+)"}?;
+    let captures = parser.captures(raw).ok_or(ParseError::NoCapture)?;
+
+    let parse = |raw: &str| raw.replace(',', "").parse::<u64>();
+
+    Ok((
+        parse(&captures[1]).map_err(ParseError::IntParseError)?,
+        parse(&captures[2]).map_err(ParseError::IntParseError)?,
+    ))
+}
+
+fn get_size(number: &str, unit: &str) -> Result<u64, ParseError<0>> {
+    let size = number.parse::<f64>().map_err(ParseError::FloatParseError)?;
+    // The site counts in powers of 1024, whether it says KB or KiB
+    let unit = match &unit[..1] {
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => 1,
+    };
+
+    Ok((size * unit as f64).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pagination_counts_listing_pages() {
+        let pagination = get_pagination("Showing 1 - 40 of 1,234 images").unwrap();
+        assert_eq!(pagination.per_page, 40);
+        assert_eq!(pagination.total, 1234);
+        assert_eq!(pagination.pages(), 31);
+
+        let pagination = get_pagination("Showing 1 - 20 of 20 images").unwrap();
+        assert_eq!(pagination.pages(), 1);
+
+        let pagination = get_pagination("Showing 41 - 80 of 80 images").unwrap();
+        assert_eq!(pagination.per_page, 40);
+        assert_eq!(pagination.pages(), 2);
+    }
+
+    #[test]
+    fn pagination_needs_the_showing_line() {
+        assert!(matches!(
+            get_pagination("No images"),
+            Err(ParseError::NoCapture)
+        ));
+    }
+
+    #[test]
+    fn image_info_is_read_from_the_caption() {
+        let info = get_image_info("scan 12 :: part.jpg :: 1280 x 1810 :: 300.5 KiB").unwrap();
+        assert_eq!(info.filename, "scan 12 :: part.jpg");
+        assert_eq!((info.width, info.height), (1280, 1810));
+        assert_eq!(info.size, 307_712);
+
+        let info = get_image_info("  1.png :: 20 x 10 :: 512 B\n").unwrap();
+        assert_eq!(info.filename, "1.png");
+        assert_eq!(info.size, 512);
+
+        assert!(get_image_info("1.png :: 20 x 10").is_err());
+    }
+
+    #[test]
+    fn original_info_is_read_from_the_link() {
+        assert_eq!(
+            get_original_info("Download original 2400 x 3400 4.5 MiB source").unwrap(),
+            (2400, 3400, 4_718_592)
+        );
+        // The site says KB but means KiB
+        assert_eq!(
+            get_original_info("Download original 800 x 600 2 KB source").unwrap(),
+            (800, 600, 2048)
+        );
+        assert!(get_original_info("Download original source").is_err());
+    }
+
+    #[test]
+    fn image_hash_comes_from_the_page_url() {
+        assert_eq!(
+            get_image_hash("https://e-hentai.org/s/0a1b2c3d4e/123456-7").unwrap(),
+            Some("0a1b2c3d4e".to_string())
+        );
+        assert_eq!(
+            get_image_hash("https://e-hentai.org/g/123456/0a1b2c3d4e/").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn nl_token_comes_from_the_reload_link() {
+        assert_eq!(
+            get_nl_token("return nl('1234-567890')").unwrap(),
+            Some("1234-567890".to_string())
+        );
+        assert_eq!(get_nl_token("return false").unwrap(), None);
+    }
+
+    #[test]
+    fn image_limit_comes_from_the_home_page() {
+        // The page is passed in as text, without its markup
+        assert_eq!(
+            get_image_limit("You are currently at 1,234\n towards a limit of 5,000.").unwrap(),
+            (1234, 5000)
+        );
+        assert!(get_image_limit("Image limits are not shown").is_err());
+    }
 }
@@ -5,10 +5,17 @@ use log::info;
 
 const PROGBAR_STYLE: &str = "{prefix:<50} [{bar:>50}] {msg} ({percent:.cyan}%)";
 const PROGBAR_BAR_STYLE: &str = "█▓▒░";
+const GROUP_STYLE: &str = "{prefix:.bold} {msg}";
 
+/// Cloning a `Progress` is cheap, every clone draws to the same
+/// terminal
+#[derive(Clone)]
 pub struct Progress {
     master: MultiProgress,
     style: ProgressStyle,
+    /// When set, new bars are drawn right under this one
+    /// instead of at the bottom
+    header: Option<ProgressBar>,
 }
 
 impl Progress {
@@ -19,14 +26,62 @@ impl Progress {
         let master = MultiProgress::new();
 
         info!("Master Progress Bar created");
-        Self { master, style }
+        Self {
+            master,
+            style,
+            header: None,
+        }
+    }
+
+    /// Makes a group headed by `prefix`. Bars added to the group
+    /// are kept together under the header, so that galleries
+    /// downloading side by side don't mix their bars
+    pub fn group<P>(&self, prefix: P) -> Self
+    where
+        P: Into<Cow<'static, str>> + Debug + Clone,
+    {
+        let header = self.master.add(ProgressBar::new(0));
+        header.set_style(ProgressStyle::with_template(GROUP_STYLE).unwrap());
+        header.set_prefix(prefix.clone());
+        header.tick();
+
+        info!("Made new progress group with prefix {:?}", prefix);
+        Self {
+            master: self.master.clone(),
+            style: self.style.clone(),
+            header: Some(header),
+        }
+    }
+
+    /// Renames the group's header, does nothing outside of a group
+    pub fn set_group_message<M>(&self, msg: M)
+    where
+        M: Into<Cow<'static, str>>,
+    {
+        if let Some(header) = &self.header {
+            header.set_message(msg);
+        }
+    }
+
+    /// Removes the group's header, does nothing outside of a group
+    pub fn finish_group(&self) {
+        if let Some(header) = &self.header {
+            header.finish_and_clear();
+        }
+    }
+
+    fn add(&self, bar: ProgressBar) -> ProgressBar {
+        match &self.header {
+            Some(header) => self.master.insert_after(header, bar),
+            None => self.master.add(bar),
+        }
     }
 
     pub fn add_prog<P>(&self, total: u64, prefix: P) -> ProgressBar
     where
         P: Into<Cow<'static, str>> + Debug + Clone,
     {
-        let new_prog = self.master.add(ProgressBar::new(total));
+        let new_prog = self.add(ProgressBar::new(total));
         new_prog.set_style(self.style.clone());
         new_prog.tick();
 
@@ -40,7 +95,7 @@ impl Progress {
     where
         P: Into<Cow<'static, str>> + Debug + Clone,
     {
-        let new_prog = self.master.add(ProgressBar::new(total));
+        let new_prog = self.add(ProgressBar::new(total));
         new_prog.set_style(style.progress_chars(PROGBAR_BAR_STYLE));
         new_prog.tick();
 
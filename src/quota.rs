@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use log::{info, warn};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Instant};
+
+use crate::extractor;
+use crate::parser;
+
+/// The image the site serves instead of the one asked
+/// for once the account's image limit is reached
+pub const LIMIT_IMAGE: &str = "509.gif";
+const HOME_URL: &str = "https://e-hentai.org/home.php";
+
+/// How long downloads are paused for after hitting the image limit
+pub const DEFAULT_PAUSE: u64 = 600;
+/// How many pauses a single image may wait out before it's given up on
+pub const MAX_PAUSES: usize = 6;
+
+lazy_static::lazy_static! {
+    /// Held for as long as downloads are paused, with the time the
+    /// last pause ended, so that everyone who hit the limit during
+    /// a pause knows it's already been waited out
+    static ref PAUSE: Mutex<Option<Instant>> = Mutex::new(None);
+}
+
+/// Whether `url` is the image the site serves past the image limit
+pub fn is_limit_image(url: &str) -> bool {
+    url.split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .ends_with(LIMIT_IMAGE)
+}
+
+/// Waits for downloads to be resumed if they're paused
+pub async fn ready() {
+    drop(PAUSE.lock().await);
+}
+
+/// Pauses every download until the image limit resets, unless a pause
+/// that ended after `since` (when the caller ran into the limit)
+/// already took care of it. Returns whether downloads should go on,
+/// which they shouldn't when pausing is turned off
+pub async fn wait_for_reset(since: Instant) -> bool {
+    let pause = get_pause();
+    if pause.is_zero() {
+        return false;
+    }
+
+    let mut resumed = PAUSE.lock().await;
+    if resumed.is_some_and(|resumed| resumed > since) {
+        return true;
+    }
+
+    loop {
+        warn!("Image limit reached, pausing downloads for {:?}", pause);
+        sleep(pause).await;
+
+        if !get_poll() {
+            break;
+        }
+
+        match get_usage().await {
+            Some((used, limit)) if used < limit => {
+                info!("Image limit is at {} of {}", used, limit);
+                break;
+            }
+            Some((used, limit)) => info!("Image limit is still at {} of {}", used, limit),
+            // Without knowing where the limit is at, trying
+            // again is the only way to find out
+            None => break,
+        }
+    }
+
+    info!("Resuming downloads");
+    *resumed = Some(Instant::now());
+    true
+}
+
+/// How much of its image limit the account has used,
+/// and what the limit is, as `home.php` says
+async fn get_usage() -> Option<(u64, u64)> {
+    let html = match extractor::get_html(HOME_URL).await {
+        Ok(html) => html,
+        Err(e) => {
+            warn!("Unable to check the image limit: {}", e);
+            return None;
+        }
+    };
+
+    let text = html.root_element().text().collect::<String>();
+    match parser::get_image_limit(&text) {
+        Ok(usage) => Some(usage),
+        Err(e) => {
+            warn!("Unable to check the image limit: {}", e);
+            None
+        }
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        fn get_pause() -> Duration {
+            Duration::from_secs(crate::CONFIG.quota.pause)
+        }
+
+        fn get_poll() -> bool {
+            crate::CONFIG.quota.poll
+        }
+    } else if #[cfg(feature = "cli")] {
+        fn get_pause() -> Duration {
+            Duration::from_secs(crate::ARGS.quota_pause)
+        }
+
+        fn get_poll() -> bool {
+            crate::ARGS.poll_quota
+        }
+    } else {
+        fn get_pause() -> Duration {
+            Duration::from_secs(DEFAULT_PAUSE)
+        }
+
+        fn get_poll() -> bool {
+            false
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::trace;
+
+pub const DEFAULT_HTML_RATE: f64 = 2.0;
+pub const DEFAULT_HTML_BURST: u32 = 4;
+pub const DEFAULT_IMAGE_RATE: f64 = 8.0;
+pub const DEFAULT_IMAGE_BURST: u32 = 16;
+
+/// Which budget a request is paid from. Gallery and image pages
+/// are served by the site itself while the images come from
+/// the image servers, so they're paced separately
+#[derive(Debug, Clone, Copy)]
+pub enum Budget {
+    Html,
+    Image,
+}
+
+/// A token bucket that refills `rate` tokens per second and
+/// holds at most `burst` of them. A `rate` of 0 disables it
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+
+        Self {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// Waits until a token is available and takes it
+    pub async fn acquire(&self) {
+        if self.rate <= 0.0 {
+            return;
+        }
+
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let (tokens, last) = &mut *state;
+                let now = Instant::now();
+
+                *tokens =
+                    (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.burst);
+                *last = now;
+
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - *tokens) / self.rate)
+            };
+
+            trace!("Rate limited, waiting {:?}", wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref HTML_BUCKET: TokenBucket = make_bucket(Budget::Html);
+    static ref IMAGE_BUCKET: TokenBucket = make_bucket(Budget::Image);
+}
+
+/// Every outgoing request should go through here first
+pub async fn acquire(budget: Budget) {
+    match budget {
+        Budget::Html => HTML_BUCKET.acquire().await,
+        Budget::Image => IMAGE_BUCKET.acquire().await,
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "config", not(test)))] {
+        fn make_bucket(budget: Budget) -> TokenBucket {
+            let network = &crate::CONFIG.network;
+
+            match budget {
+                Budget::Html => TokenBucket::new(network.html_rate, network.html_burst),
+                Budget::Image => TokenBucket::new(network.image_rate, network.image_burst),
+            }
+        }
+    } else if #[cfg(all(feature = "cli", not(test)))] {
+        fn make_bucket(budget: Budget) -> TokenBucket {
+            match budget {
+                Budget::Html => TokenBucket::new(crate::ARGS.html_rate, crate::ARGS.html_burst),
+                Budget::Image => TokenBucket::new(crate::ARGS.image_rate, crate::ARGS.image_burst),
+            }
+        }
+    } else {
+        fn make_bucket(budget: Budget) -> TokenBucket {
+            match budget {
+                Budget::Html => TokenBucket::new(DEFAULT_HTML_RATE, DEFAULT_HTML_BURST),
+                Budget::Image => TokenBucket::new(DEFAULT_IMAGE_RATE, DEFAULT_IMAGE_BURST),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn burst_is_used_up_before_waiting() {
+        let bucket = TokenBucket::new(20.0, 3);
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            bucket.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(25));
+
+        // The next token takes 1/20th of a second to refill
+        bucket.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[tokio::test]
+    async fn zero_rate_never_waits() {
+        let bucket = TokenBucket::new(0.0, 1);
+
+        let start = Instant::now();
+        for _ in 0..1000 {
+            bucket.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use reqwest::StatusCode;
+
+/// Attempts in total, including the first one
+pub const DEFAULT_ATTEMPTS: u32 = 4;
+/// The delay (in milliseconds) before the first retry, every
+/// retry after that doubles it
+pub const DEFAULT_BACKOFF: u64 = 500;
+/// The most we're willing to wait (in milliseconds) between two attempts
+pub const DEFAULT_MAX_BACKOFF: u64 = 30_000;
+
+/// Errors that may go away if the same operation is tried again
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, backoff: u64, max_backoff: u64) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            backoff: Duration::from_millis(backoff),
+            max_backoff: Duration::from_millis(max_backoff.max(backoff)),
+        }
+    }
+
+    /// Runs `op` until it succeeds, fails with an error that isn't
+    /// worth retrying, or runs out of attempts. `what` is only used
+    /// for logging
+    pub async fn run<F, Fut, T, E>(&self, what: &str, mut op: F) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Retryable + Display,
+    {
+        let mut attempt = 1;
+
+        loop {
+            match op().await {
+                Err(e) if attempt < self.max_attempts && e.is_retryable() => {
+                    let delay = self.delay_for(attempt);
+                    warn!(
+                        "Attempt {} of {} for {} failed, retrying in {:?}: {}",
+                        attempt, self.max_attempts, what, delay, e
+                    );
+
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Exponential backoff with "equal jitter": half of the delay is
+    /// fixed and the other half is random, so that workers that failed
+    /// together don't all come back at the same time
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_backoff);
+        let half = delay / 2;
+
+        half + half.mul_f64(jitter())
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_ATTEMPTS, DEFAULT_BACKOFF, DEFAULT_MAX_BACKOFF)
+    }
+}
+
+/// Classifies a `reqwest::Error`: rate limiting, server errors,
+/// timeouts and dropped connections are all considered transient
+pub fn is_retryable_request(err: &reqwest::Error) -> bool {
+    match err.status() {
+        Some(status) => is_retryable_status(status),
+        None => err.is_timeout() || err.is_connect() || err.is_request() || err.is_body(),
+    }
+}
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error()
+}
+
+/// A number between 0 and 1. This doesn't need to be good
+/// randomness, it only needs to differ between workers
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+
+    (nanos % 1000) as f64 / 1000.0
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "config", not(test)))] {
+        pub fn get_policy() -> RetryPolicy {
+            let network = &crate::CONFIG.network;
+
+            RetryPolicy::new(network.attempts, network.backoff, network.max_backoff)
+        }
+    } else if #[cfg(all(feature = "cli", not(test)))] {
+        pub fn get_policy() -> RetryPolicy {
+            RetryPolicy::new(crate::ARGS.attempts, crate::ARGS.backoff, crate::ARGS.max_backoff)
+        }
+    } else {
+        pub fn get_policy() -> RetryPolicy {
+            RetryPolicy::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Failure(bool);
+
+    impl Display for Failure {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "failure")
+        }
+    }
+
+    impl Retryable for Failure {
+        fn is_retryable(&self) -> bool {
+            self.0
+        }
+    }
+
+    /// Jitter takes off up to half of the delay
+    fn assert_delay(policy: &RetryPolicy, attempt: u32, millis: u64) {
+        let delay = policy.delay_for(attempt);
+        let full = Duration::from_millis(millis);
+
+        assert!(
+            delay >= full / 2 && delay <= full,
+            "attempt {}: {:?} isn't within {:?}",
+            attempt,
+            delay,
+            full
+        );
+    }
+
+    #[test]
+    fn delays_double_up_to_the_max() {
+        let policy = RetryPolicy::new(10, 500, 3_000);
+
+        assert_delay(&policy, 1, 500);
+        assert_delay(&policy, 2, 1_000);
+        assert_delay(&policy, 3, 2_000);
+        assert_delay(&policy, 4, 3_000);
+        assert_delay(&policy, 10, 3_000);
+        assert_delay(&policy, u32::MAX, 3_000);
+    }
+
+    #[test]
+    fn only_transient_statuses_are_retried() {
+        for status in [
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::REQUEST_TIMEOUT,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ] {
+            assert!(is_retryable_status(status), "{}", status);
+        }
+
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::FORBIDDEN,
+            StatusCode::NOT_FOUND,
+            StatusCode::GONE,
+            StatusCode::RANGE_NOT_SATISFIABLE,
+        ] {
+            assert!(!is_retryable_status(status), "{}", status);
+        }
+    }
+
+    #[tokio::test]
+    async fn attempts_stop_at_the_limit_or_a_lasting_error() {
+        let policy = RetryPolicy::new(3, 0, 0);
+
+        let mut attempts = 0;
+        let res: Result<(), _> = policy
+            .run("test", || {
+                attempts += 1;
+                async { Err(Failure(true)) }
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let res: Result<(), _> = policy
+            .run("test", || {
+                attempts += 1;
+                async { Err(Failure(false)) }
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let res = policy
+            .run("test", || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt < 2 {
+                        Err(Failure(true))
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            })
+            .await;
+        assert_eq!(res.unwrap(), 2);
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt::Display;
+
+use futures_util::{stream, StreamExt};
+use log::info;
+
+use crate::downloader::{self, DownloadError, DownloadResponse};
+use crate::extractor::{self, ExtractionError};
+use crate::gallery::Gallery;
+use crate::parser;
+use crate::progress::Progress;
+use crate::state::{self, GalleryStatus};
+
+/// Galleries worked on at the same time
+pub const DEFAULT_GALLERY_WORKERS: usize = 2;
+
+#[derive(Debug)]
+pub enum GalleryError<'a> {
+    ExtractionError(ExtractionError<'a>),
+    DownloadError(DownloadError),
+}
+
+impl<'a> Display for GalleryError<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ExtractionError(e) => write!(f, "{}", e),
+            Self::DownloadError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<'a> GalleryError<'a> {
+    /// What kind of problem this is, for the summary of a run
+    pub fn category(&self) -> &'static str {
+        match self {
+            Self::ExtractionError(e) => e.category(),
+            Self::DownloadError(_) => "download",
+        }
+    }
+}
+
+pub type GalleryResult<'a> = Result<(Gallery, DownloadResponse), GalleryError<'a>>;
+
+/// Extracts and downloads every gallery in `urls`, running up to the
+/// configured amount of them side by side. Results come back in the
+/// order the galleries finish, each paired with the url it's for
+pub async fn run<'a, const CHUNK_SIZE: usize>(
+    urls: &'a [String],
+    m_prog: &Progress,
+) -> Vec<(&'a String, GalleryResult<'a>)> {
+    let gallery_prog = m_prog.add_prog(urls.len() as u64, "Getting Galleries");
+
+    let results = stream::iter(urls)
+        .map(|url| async move {
+            let group = m_prog.group(url.clone());
+            let result = download::<CHUNK_SIZE>(url, &group).await;
+            group.finish_group();
+
+            (url, result)
+        })
+        .buffer_unordered(get_gallery_workers())
+        .inspect(|_| gallery_prog.inc(1))
+        .collect::<Vec<_>>()
+        .await;
+    gallery_prog.finish_and_clear();
+
+    results
+}
+
+/// Works through a single gallery, keeping the state journal
+/// up to date with how far it got
+async fn download<'a, const CHUNK_SIZE: usize>(
+    url: &'a String,
+    m_prog: &Progress,
+) -> GalleryResult<'a> {
+    let gid = parser::get_gallery_id(url).ok().map(|(gid, _)| gid);
+    let set_status = |status| {
+        if let Some(gid) = gid {
+            state::get_state().set_gallery(gid, status);
+        }
+    };
+
+    set_status(GalleryStatus::Extracting);
+    let result = try_download::<CHUNK_SIZE>(url, m_prog, &set_status).await;
+    set_status(match result {
+        Ok(_) => GalleryStatus::Packaged,
+        Err(_) => GalleryStatus::Failed,
+    });
+
+    result
+}
+
+async fn try_download<'a, const CHUNK_SIZE: usize>(
+    url: &'a String,
+    m_prog: &Progress,
+    set_status: &impl Fn(GalleryStatus),
+) -> GalleryResult<'a> {
+    info!("fetching data for {:?}", url);
+    let mut gallery = extractor::get_gallery(url, m_prog)
+        .await
+        .map_err(GalleryError::ExtractionError)?;
+
+    set_status(GalleryStatus::Downloading);
+    info!("downloading gallery {:?}", gallery.title());
+    m_prog.set_group_message(gallery.title().clone());
+    let downloads = downloader::download_gallery::<CHUNK_SIZE>(&mut gallery, m_prog)
+        .await
+        .map_err(GalleryError::DownloadError)?;
+
+    Ok((gallery, downloads))
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        fn get_gallery_workers() -> usize {
+            crate::CONFIG.download.galleries.max(1)
+        }
+    } else if #[cfg(feature = "cli")] {
+        fn get_gallery_workers() -> usize {
+            crate::ARGS.galleries.max(1)
+        }
+    } else {
+        fn get_gallery_workers() -> usize {
+            DEFAULT_GALLERY_WORKERS
+        }
+    }
+}
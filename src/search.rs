@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+
+use log::{debug, info};
+use scraper::Html;
+
+use crate::extractor::{get_html, ExtractionError};
+use crate::parser;
+use crate::progress::Progress;
+
+/// Walks every result page of a search, tag or uploader `url`
+/// and collects the galleries listed on them, in the order the
+/// site lists them. Stops early once `max` galleries are found
+pub async fn get_galleries<'a>(
+    url: &str,
+    max: Option<usize>,
+    progress: &Progress,
+) -> Result<Vec<String>, ExtractionError<'a>> {
+    let prog = progress.add_prog(max.unwrap_or(0) as u64, format!("Searching {}", url));
+    let mut seen = HashSet::new();
+    let mut galleries = vec![];
+    let mut next = Some(url.to_string());
+    let mut page = 0;
+
+    info!("Enumerating galleries from {:?}", url);
+    while let Some(url) = next.take() {
+        page += 1;
+        prog.set_message(format!("page {}", page));
+
+        let html = get_html(url.as_str()).await?;
+        let mut found = 0;
+
+        for gallery in get_listed(&html)? {
+            let (gid, _) =
+                parser::get_gallery_id(&gallery).map_err(ExtractionError::DataParseError)?;
+            if !seen.insert(gid) {
+                continue;
+            }
+
+            galleries.push(gallery);
+            found += 1;
+            prog.inc(1);
+
+            if max.is_some_and(|max| galleries.len() >= max) {
+                break;
+            }
+        }
+        debug!("Found {} new galleries on {}", found, url);
+
+        // A page that adds nothing means we're going in circles
+        if found == 0 || max.is_some_and(|max| galleries.len() >= max) {
+            break;
+        }
+
+        next = get_next_page(&html)?;
+    }
+    prog.finish_and_clear();
+
+    info!("Found {} galleries from {:?}", galleries.len(), url);
+    Ok(galleries)
+}
+
+/// Every gallery linked from a result page. Thumbnail and extended
+/// views link to the same gallery more than once, so this may contain
+/// duplicates
+fn get_listed<'a>(html: &Html) -> Result<Vec<String>, ExtractionError<'a>> {
+    let sel = compile!(selector "table.itg a[href], div.itg a[href]")?;
+    let links = html
+        .select(&sel)
+        .filter_map(|link| link.value().attr("href"))
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    parser::get_all_galleries(&links).map_err(ExtractionError::DataParseError)
+}
+
+/// Newer result pages paginate with a `next=<gid>` cursor behind
+/// `#unext`, older ones with page numbers in the `.ptt` table
+fn get_next_page<'a>(html: &Html) -> Result<Option<String>, ExtractionError<'a>> {
+    let cursor = compile!(selector "a#unext[href]")?;
+    let numbered = compile!(selector "table.ptt td:last-child a[href]")?;
+
+    Ok(html
+        .select(&cursor)
+        .next()
+        .or_else(|| html.select(&numbered).next())
+        .and_then(|link| link.value().attr("href"))
+        .map(|href| href.to_string()))
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, prelude::*, BufReader};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use log::{debug, info, warn};
+
+use crate::gallery::Image;
+
+pub const DEFAULT_STATE_FILE: &str = "ehentai-dl.state";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GalleryStatus {
+    Pending,
+    Extracting,
+    Downloading,
+    Packaged,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageStatus {
+    Downloaded,
+    Failed,
+}
+
+impl Display for GalleryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Pending => "pending",
+                Self::Extracting => "extracting",
+                Self::Downloading => "downloading",
+                Self::Packaged => "packaged",
+                Self::Failed => "failed",
+            }
+        )
+    }
+}
+
+impl FromStr for GalleryStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "extracting" => Ok(Self::Extracting),
+            "downloading" => Ok(Self::Downloading),
+            "packaged" => Ok(Self::Packaged),
+            "failed" => Ok(Self::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ImageStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Downloaded => "downloaded",
+                Self::Failed => "failed",
+            }
+        )
+    }
+}
+
+impl FromStr for ImageStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "downloaded" => Ok(Self::Downloaded),
+            "failed" => Ok(Self::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Where every gallery and image stands, kept in an append-only
+/// journal so that a run that dies halfway through can be picked
+/// up by the next one. Each line is one of:
+///
+/// ```text
+/// G <gid> <status>
+/// I <gid> <page> <status> <filename>[\t<original name>[\t<width>\t<height>\t<size>]]
+/// ```
+///
+/// and later lines override earlier ones. Whatever comes after the
+/// filename is left out when it isn't known, or for the original
+/// name, when the image was saved under it
+pub struct StateStore {
+    journal: Option<Mutex<File>>,
+    galleries: Mutex<HashMap<u64, GalleryStatus>>,
+    images: Mutex<HashMap<(u64, usize), ImageRecord>>,
+}
+
+struct ImageRecord {
+    status: ImageStatus,
+    image: Image,
+}
+
+impl StateStore {
+    pub fn open(path: &PathBuf) -> Result<Self, io::Error> {
+        let mut galleries = HashMap::new();
+        let mut images = HashMap::new();
+
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    replay(&line?, &mut galleries, &mut images);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        info!(
+            "Loaded state for {} galleries and {} images from {:?}",
+            galleries.len(),
+            images.len(),
+            path
+        );
+
+        let journal = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(Self {
+            journal: Some(Mutex::new(journal)),
+            galleries: Mutex::new(galleries),
+            images: Mutex::new(images),
+        })
+    }
+
+    /// A store that remembers nothing past this run, used when
+    /// the journal can't be opened
+    fn in_memory() -> Self {
+        Self {
+            journal: None,
+            galleries: Mutex::new(HashMap::new()),
+            images: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn gallery(&self, gid: u64) -> Option<GalleryStatus> {
+        self.galleries.lock().unwrap().get(&gid).copied()
+    }
+
+    pub fn set_gallery(&self, gid: u64, status: GalleryStatus) {
+        debug!("Gallery {} is now {}", gid, status);
+
+        self.galleries.lock().unwrap().insert(gid, status);
+        self.append(format!("G {} {}", gid, status));
+    }
+
+    /// The images of `gid` that were downloaded already, keyed by
+    /// their index in the gallery. Only what the journal keeps of
+    /// them is filled in, their url isn't
+    pub fn downloaded_images(&self, gid: u64) -> HashMap<usize, Image> {
+        self.images
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((id, _), record)| *id == gid && record.status == ImageStatus::Downloaded)
+            .map(|((_, page), record)| (*page, record.image.clone()))
+            .collect()
+    }
+
+    pub fn set_image(&self, gid: u64, page: usize, status: ImageStatus, image: &Image) {
+        let mut line = format!("I {} {} {} {}", gid, page, status, image.get_filename());
+        let original = image.get_original_name();
+        let details = image
+            .get_dimensions()
+            .zip(image.get_declared_size())
+            .map(|((width, height), size)| format!("\t{}\t{}\t{}", width, height, size));
+
+        if original != image.get_filename() || details.is_some() {
+            line.push('\t');
+            line.push_str(original);
+        }
+        if let Some(details) = details {
+            line.push_str(&details);
+        }
+
+        let mut image = image.clone();
+        image.set_url(String::new());
+        self.images
+            .lock()
+            .unwrap()
+            .insert((gid, page), ImageRecord { status, image });
+        self.append(line);
+    }
+
+    fn append(&self, line: String) {
+        if let Some(journal) = &self.journal {
+            let mut journal = journal.lock().unwrap();
+
+            if let Err(e) = writeln!(journal, "{}", line).and_then(|_| journal.flush()) {
+                warn!("Unable to write to the state journal: {}", e);
+            }
+        }
+    }
+}
+
+fn replay(
+    line: &str,
+    galleries: &mut HashMap<u64, GalleryStatus>,
+    images: &mut HashMap<(u64, usize), ImageRecord>,
+) {
+    let mut fields = line.splitn(5, ' ');
+
+    match (
+        fields.next(),
+        fields.next().and_then(|gid| gid.parse().ok()),
+    ) {
+        (Some("G"), Some(gid)) => {
+            if let Some(status) = fields.next().and_then(|s| s.parse().ok()) {
+                galleries.insert(gid, status);
+            }
+        }
+        (Some("I"), Some(gid)) => {
+            let page = fields.next().and_then(|p| p.parse().ok());
+            let status = fields.next().and_then(|s| s.parse().ok());
+
+            if let (Some(page), Some(status), Some(names)) = (page, status, fields.next()) {
+                images.insert(
+                    (gid, page),
+                    ImageRecord {
+                        status,
+                        image: replay_image(names),
+                    },
+                );
+            }
+        }
+        // A line cut short by a crash, or something we don't
+        // know about. Either way there's nothing to restore
+        _ => debug!("Ignoring state journal line {:?}", line),
+    }
+}
+
+/// Rebuilds an image from what follows the status of an `I` line
+fn replay_image(raw: &str) -> Image {
+    let mut fields = raw.split('\t');
+    let filename = fields.next().unwrap_or_default();
+    let original = fields.next().unwrap_or(filename);
+
+    let mut image = Image::new(&String::new());
+    image.set_filename(filename.to_string());
+    image.set_original_name(original.to_string());
+
+    let mut numbers = fields.map(|field| field.parse::<u64>().ok());
+    if let (Some(Some(width)), Some(Some(height)), Some(Some(size))) =
+        (numbers.next(), numbers.next(), numbers.next())
+    {
+        image.set_dimensions(Some((width as u32, height as u32)));
+        image.set_declared_size(Some(size));
+    }
+
+    image
+}
+
+lazy_static::lazy_static! {
+    static ref STATE: StateStore = StateStore::open(&get_state_file())
+        .unwrap_or_else(|e| {
+            warn!("Unable to open the state journal, progress won't be saved: {}", e);
+            StateStore::in_memory()
+        });
+}
+
+pub fn get_state() -> &'static StateStore {
+    &STATE
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        fn get_state_file() -> PathBuf {
+            crate::CONFIG.download.state.clone()
+        }
+    } else if #[cfg(feature = "cli")] {
+        fn get_state_file() -> PathBuf {
+            crate::ARGS.state.clone()
+        }
+    } else {
+        fn get_state_file() -> PathBuf {
+            PathBuf::from(DEFAULT_STATE_FILE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(filename: &str, original: &str, details: Option<((u32, u32), u64)>) -> Image {
+        let mut image = Image::new(&"https://e-hentai.org/s/0123456789/1-1".to_string());
+        image.set_filename(filename.to_string());
+        image.set_original_name(original.to_string());
+        image.set_dimensions(details.map(|(dimensions, _)| dimensions));
+        image.set_declared_size(details.map(|(_, size)| size));
+
+        image
+    }
+
+    fn assert_same(got: &Image, expected: &Image) {
+        assert_eq!(got.get_filename(), expected.get_filename());
+        assert_eq!(got.get_original_name(), expected.get_original_name());
+        assert_eq!(got.get_dimensions(), expected.get_dimensions());
+        assert_eq!(got.get_declared_size(), expected.get_declared_size());
+    }
+
+    #[test]
+    fn journal_replays_what_was_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_STATE_FILE);
+
+        let plain = image("1.jpg", "1.jpg", None);
+        let renamed = image("02 - image (2).jpg", "image.jpg", None);
+        let detailed = image("3.png", "3.png", Some(((1280, 1810), 412_345)));
+        let failed = image("4.jpg", "4.jpg", None);
+
+        let state = StateStore::open(&path).unwrap();
+        state.set_gallery(1, GalleryStatus::Downloading);
+        state.set_gallery(2, GalleryStatus::Pending);
+        state.set_gallery(2, GalleryStatus::Packaged);
+        state.set_image(1, 0, ImageStatus::Downloaded, &plain);
+        state.set_image(1, 1, ImageStatus::Downloaded, &renamed);
+        state.set_image(1, 2, ImageStatus::Downloaded, &detailed);
+        state.set_image(1, 3, ImageStatus::Downloaded, &failed);
+        state.set_image(1, 3, ImageStatus::Failed, &failed);
+        state.set_image(2, 0, ImageStatus::Downloaded, &plain);
+        drop(state);
+
+        let state = StateStore::open(&path).unwrap();
+        assert_eq!(state.gallery(1), Some(GalleryStatus::Downloading));
+        assert_eq!(state.gallery(2), Some(GalleryStatus::Packaged));
+        assert_eq!(state.gallery(3), None);
+
+        let images = state.downloaded_images(1);
+        assert_eq!(images.len(), 3);
+        assert_same(&images[&0], &plain);
+        assert_same(&images[&1], &renamed);
+        assert_same(&images[&2], &detailed);
+        assert!(!images.contains_key(&3));
+
+        assert_eq!(state.downloaded_images(2).len(), 1);
+    }
+
+    #[test]
+    fn journal_skips_broken_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(DEFAULT_STATE_FILE);
+
+        std::fs::write(
+            &path,
+            "G 1 packaged\n\
+             G 2 sideways\n\
+             G x packaged\n\
+             I 1 0 downloaded 1.jpg\n\
+             I 1 1 downloaded\n\
+             I 1 2 lost 3.jpg\n\
+             Z 1 2\n\
+             I 1 3 downl",
+        )
+        .unwrap();
+
+        let state = StateStore::open(&path).unwrap();
+        assert_eq!(state.gallery(1), Some(GalleryStatus::Packaged));
+        assert_eq!(state.gallery(2), None);
+
+        let images = state.downloaded_images(1);
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[&0].get_filename(), "1.jpg");
+        assert_eq!(images[&0].get_original_name(), "1.jpg");
+    }
+
+    #[test]
+    fn details_need_every_number() {
+        let image = replay_image("1.jpg\t1.jpg\t1280\t\t1000");
+        assert_eq!(image.get_dimensions(), None);
+        assert_eq!(image.get_declared_size(), None);
+
+        let image = replay_image("1.jpg\tscan 1.jpg\t1280\t1810\t1000");
+        assert_eq!(image.get_original_name(), "scan 1.jpg");
+        assert_eq!(image.get_dimensions(), Some((1280, 1810)));
+        assert_eq!(image.get_declared_size(), Some(1000));
+    }
+}
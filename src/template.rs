@@ -0,0 +1,216 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use crate::gallery::Gallery;
+use crate::sanitize::sanitize;
+
+/// Galleries go straight into the output directory, under their title
+pub const DEFAULT_TEMPLATE: &str = "{title}";
+pub const DEFAULT_OUTPUT: &str = ".";
+
+/// What a tag field turns into when the gallery has no such tag
+const MISSING_FIELD: &str = "unknown";
+
+#[derive(Debug)]
+pub enum TemplateError {
+    UnknownField(String),
+    UnclosedField(String),
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "error while {}",
+            match self {
+                Self::UnknownField(e) => format!("filling path template: no such field {:?}", e),
+                Self::UnclosedField(e) => {
+                    format!("parsing path template: {:?} is missing a closing brace", e)
+                }
+            }
+        )
+    }
+}
+
+/// Turns `template` into the path `gallery` is downloaded to,
+/// relative to the output directory.
+///
+/// Fields are written as `{name}` and may be any of `title`,
+/// `title_jpn`, `category`, `uploader`, `gid` and `token`, or a tag
+/// namespace such as `artist` or `parody`, in which case all of the
+/// gallery's tags in it are listed. Every `/`-separated component is
+/// sanitized on its own, so a field can't introduce new directories
+pub fn render(template: &str, gallery: &Gallery) -> Result<PathBuf, TemplateError> {
+    let mut path = PathBuf::new();
+
+    for component in template.split('/') {
+        let component = render_component(component, gallery)?;
+        let component = sanitize(component.trim());
+
+        match component.as_str() {
+            "" => continue,
+            // Nothing a gallery says should make us
+            // write outside of the output directory
+            "." | ".." => path.push(component.replace('.', "_")),
+            _ => path.push(component),
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        path.push(gallery.gid().to_string());
+    }
+
+    Ok(path)
+}
+
+/// Catches mistakes in `template` before any gallery is downloaded
+pub fn validate(template: &str) -> Result<(), TemplateError> {
+    render(template, &Gallery::new()).map(|_| ())
+}
+
+fn render_component(component: &str, gallery: &Gallery) -> Result<String, TemplateError> {
+    let mut rendered = String::new();
+    let mut rest = component;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| TemplateError::UnclosedField(rest[start..].to_string()))?;
+        rendered.push_str(&get_field(&rest[start + 1..start + end], gallery)?);
+
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+fn get_field(field: &str, gallery: &Gallery) -> Result<String, TemplateError> {
+    Ok(match field {
+        "title" => gallery.title().clone(),
+        "title_jpn" => gallery.title_jpn().clone(),
+        "category" => gallery.category().clone(),
+        "uploader" => gallery.uploader().clone(),
+        "gid" => gallery.gid().to_string(),
+        "token" => gallery.token().clone(),
+        namespace if is_namespace(namespace) => {
+            let values = gallery
+                .tags()
+                .into_iter()
+                .filter(|tag| tag.tag_type().to_string() == namespace)
+                .map(|tag| tag.tag_value().clone())
+                .collect::<Vec<_>>();
+
+            if values.is_empty() {
+                MISSING_FIELD.to_string()
+            } else {
+                values.join(", ")
+            }
+        }
+        other => return Err(TemplateError::UnknownField(other.to_string())),
+    })
+}
+
+fn is_namespace(field: &str) -> bool {
+    matches!(
+        field,
+        "reclass"
+            | "parody"
+            | "character"
+            | "language"
+            | "artist"
+            | "group"
+            | "cosplayer"
+            | "male"
+            | "female"
+            | "mixed"
+            | "other"
+    )
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "config")] {
+        pub fn get_template() -> String {
+            crate::CONFIG.download.template.clone()
+        }
+
+        pub fn get_output() -> PathBuf {
+            crate::CONFIG.download.output.clone()
+        }
+    } else if #[cfg(feature = "cli")] {
+        pub fn get_template() -> String {
+            crate::ARGS.template.clone()
+        }
+
+        pub fn get_output() -> PathBuf {
+            crate::ARGS.output.clone()
+        }
+    } else {
+        pub fn get_template() -> String {
+            DEFAULT_TEMPLATE.to_string()
+        }
+
+        pub fn get_output() -> PathBuf {
+            PathBuf::from(DEFAULT_OUTPUT)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gallery() -> Gallery {
+        let mut gallery = Gallery::new();
+        gallery.set_id(123456, "0a1b2c3d4e".to_string());
+        gallery.set_title("AC/DC Fan Book".to_string());
+        gallery.set_category("Doujinshi".to_string());
+        gallery.add_tag("artist".to_string(), "foo".to_string());
+        gallery.add_tag("artist".to_string(), "bar".to_string());
+        gallery.add_tag("language".to_string(), "english".to_string());
+
+        gallery
+    }
+
+    #[test]
+    fn fields_are_filled_in() {
+        let path = render("{category}/{artist}/[{gid}] {title}", &gallery()).unwrap();
+        assert_eq!(
+            path,
+            PathBuf::from("Doujinshi/foo, bar/[123456] AC_DC Fan Book")
+        );
+
+        let path = render("{parody}/{title}", &gallery()).unwrap();
+        assert_eq!(path, PathBuf::from("unknown/AC_DC Fan Book"));
+    }
+
+    #[test]
+    fn paths_stay_in_the_output_directory() {
+        let mut gallery = gallery();
+        gallery.set_title("..".to_string());
+
+        assert_eq!(
+            render("/{title}/../{title}", &gallery).unwrap(),
+            PathBuf::from("__/__/__")
+        );
+        assert_eq!(
+            render("{title_jpn}", &gallery).unwrap(),
+            PathBuf::from("123456")
+        );
+    }
+
+    #[test]
+    fn mistakes_are_caught() {
+        assert!(validate(DEFAULT_TEMPLATE).is_ok());
+        assert!(matches!(
+            validate("{titel}"),
+            Err(TemplateError::UnknownField(field)) if field == "titel"
+        ));
+        assert!(matches!(
+            validate("{artist}/{title"),
+            Err(TemplateError::UnclosedField(_))
+        ));
+    }
+}
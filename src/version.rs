@@ -27,6 +27,8 @@ pub fn get_features() -> Vec<String> {
 
     #[cfg(feature = "aniyomi")]
     res.push("aniyomi");
+    #[cfg(feature = "api")]
+    res.push("api");
     #[cfg(feature = "zip")]
     res.push("zip");
     #[cfg(feature = "config")]
@@ -3,9 +3,12 @@ use std::fs::{File, OpenOptions};
 use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
 
+use crate::atomic;
 use crate::sanitize::sanitize;
 use log::{debug, info, trace};
 
+#[cfg(feature = "zip")]
+use tempfile::TempPath;
 #[cfg(feature = "zip")]
 use zip::{write::*, CompressionMethod};
 
@@ -19,6 +22,8 @@ type ZipFile = ZipWriter<File>;
 #[cfg(feature = "zip")]
 pub enum ZipError {
     ZipOpenError(io::Error, String),
+    ArchiveReadError(zip::result::ZipError, String),
+    FinishError(zip::result::ZipError, String),
     AddDirError(zip::result::ZipError, String),
     ReadError(io::Error),
     WriteError(io::Error),
@@ -36,6 +41,8 @@ impl Display for ZipError {
             "error while {}",
             match self {
                 Self::ZipOpenError(e, f) => format!("opening zip file {:?}: {}", f, e),
+                Self::ArchiveReadError(e, f) => format!("reading zip file {:?}: {}", f, e),
+                Self::FinishError(e, f) => format!("finishing zip file {:?}: {}", f, e),
                 Self::AddDirError(e, f) => format!("adding directory {:?}: {}", f, e),
                 Self::ReadError(e) => format!("reading file: {}", e),
                 Self::WriteError(e) => format!("writing file: {}", e),
@@ -48,27 +55,91 @@ impl Display for ZipError {
     }
 }
 
+/// A zip file being written, which only shows up
+/// under its name once it's finished
+#[cfg(feature = "zip")]
+pub struct Archive {
+    writer: ZipFile,
+    temp: TempPath,
+    path: PathBuf,
+}
+
+#[cfg(feature = "zip")]
+impl Archive {
+    pub fn finish(mut self) -> Result<(), ZipError> {
+        self.writer
+            .finish()
+            .map_err(|e| ZipError::FinishError(e, self.path.to_string_lossy().to_string()))?;
+        self.temp.persist(&self.path).map_err(|e| {
+            ZipError::ZipOpenError(e.error, self.path.to_string_lossy().to_string())
+        })?;
+
+        info!("Finished writing {:?}", self.path);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "zip")]
+pub fn make_zip<P: AsRef<Path>>(path: &P) -> Result<Archive, ZipError> {
+    let path = path.as_ref();
+    let filename = path.with_file_name(sanitize(&path.file_name().unwrap().to_string_lossy()));
+
+    // Whether an existing archive may be replaced
+    // was settled before the gallery was downloaded
+    let (file, temp) = atomic::create(&filename)
+        .map_err(|e| ZipError::ZipOpenError(e, filename.to_string_lossy().to_string()))?
+        .into_parts();
+
+    Ok(Archive {
+        writer: ZipWriter::new(file),
+        temp,
+        path: filename,
+    })
+}
+
+/// The contents of the first file in the archive at `path`
+/// that's called `file_name`, in whichever directory it is
 #[cfg(feature = "zip")]
-pub fn make_zip<P: AsRef<Path> + ToString>(filename: &P) -> Result<ZipFile, ZipError> {
-    let filename = sanitize(&filename.to_string());
-    let file = OpenOptions::new()
-        .create_new(true)
-        .write(true)
-        .open(&filename)
-        .map_err(|e| ZipError::ZipOpenError(e, filename.to_string()))?;
-
-    Ok(ZipWriter::new(file))
+pub fn read_entry(path: &Path, file_name: &str) -> Result<Option<String>, ZipError> {
+    let display = path.to_string_lossy().to_string();
+    let file = File::open(path).map_err(|e| ZipError::ZipOpenError(e, display.clone()))?;
+    let mut archive =
+        zip::ZipArchive::new(file).map_err(|e| ZipError::ArchiveReadError(e, display.clone()))?;
+
+    let Some(entry) = archive
+        .file_names()
+        .find(|name| name.rsplit('/').next() == Some(file_name))
+        .map(|name| name.to_string())
+    else {
+        return Ok(None);
+    };
+
+    let mut contents = String::new();
+    archive
+        .by_name(&entry)
+        .map_err(|e| ZipError::ArchiveReadError(e, display))?
+        .read_to_string(&mut contents)
+        .map_err(ZipError::ReadError)?;
+
+    Ok(Some(contents))
 }
 
+/// Adds `path` to `arch`, named after where it is relative to `base`
 #[cfg(feature = "zip")]
-pub fn add_file<P, const CHUNK_SIZE: usize>(arch: &mut ZipFile, path: &P) -> Result<usize, ZipError>
+pub fn add_file<P, const CHUNK_SIZE: usize>(
+    arch: &mut Archive,
+    path: &P,
+    base: &Path,
+) -> Result<usize, ZipError>
 where
     P: AsRef<Path>,
 {
     trace!("Chunk size provided is {}", CHUNK_SIZE);
 
-    let path = path
-        .as_ref()
+    let path = path.as_ref();
+    let name = path
+        .strip_prefix(base)
+        .unwrap_or(path)
         .components()
         .filter_map(|c| {
             if c.as_os_str().to_string_lossy() == "." {
@@ -86,15 +157,14 @@ where
     info!("Adding file {:?}", path.display());
 
     if path.is_file() {
-        let path = path.to_str().unwrap();
-
         let mut file = OpenOptions::new()
             .read(true)
             .open(path)
             .map_err(|e| ZipError::ReadError(e))?;
 
         let mut buf = [0; CHUNK_SIZE];
-        arch.start_file(path, compression_opts)
+        arch.writer
+            .start_file(name.to_string_lossy(), compression_opts)
             .map_err(|e| ZipError::StartFileError {
                 error: e,
                 compression: COMPRESSION_MODE,
@@ -109,6 +179,7 @@ where
             }
 
             let written = arch
+                .writer
                 .write(
                     &buf.iter()
                         .take(read)
@@ -134,9 +205,10 @@ where
 
         debug!("Written {:?} to archive", path);
         return Ok(written_bytes);
-    } else if !path.starts_with("./") {
-        arch.add_directory(path.as_os_str().to_string_lossy(), compression_opts)
-            .map_err(|e| ZipError::AddDirError(e, path.to_string_lossy().to_string()))?;
+    } else if !name.as_os_str().is_empty() {
+        arch.writer
+            .add_directory(name.to_string_lossy(), compression_opts)
+            .map_err(|e| ZipError::AddDirError(e, name.to_string_lossy().to_string()))?;
     }
 
     Ok(0)